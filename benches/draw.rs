@@ -0,0 +1,78 @@
+//! Benchmark of drawing a bar whose clock ticks every second.
+//!
+//! Run with `cargo bench`. Text is only shaped the first time it is
+//! shown, and each way of drawing is timed with the layouts cleared before
+//! every round, then kept across rounds. Clearing them shapes all text
+//! as if nothing was kept.
+
+extern crate lemonade;
+
+#[path = "../src/bin/lemon.rs"]
+#[allow(dead_code)]
+mod lemon;
+
+use std::time::{Duration, Instant};
+
+use lemonade::Bar;
+use lemonade::format::FormatItem;
+use lemonade::window::Headless;
+use lemon::LemonParser;
+
+const ROUNDS: u32 = 200;
+
+/// The line shown at second `s`, like that of a status script.
+fn line(s: u32) -> String {
+    format!(concat!("%{{l}}%{{F#ccc}} 1  2  3  4  5 %{{F-}}",
+                    "%{{U#f80 +u}} firefox - Mozilla Firefox %{{-u}}",
+                    "%{{c}}%{{A:cal:}} Sun 18 Oct  12:34:{:02} %{{A}}",
+                    "%{{r}} cpu 12 | mem 3.4G | vol 45 | bat 87 "),
+            s % 60)
+}
+
+fn lines() -> Vec<Vec<FormatItem>> {
+    let mut parser = LemonParser::new();
+    (0..ROUNDS).map(|s| parser.parse(&line(s))).collect()
+}
+
+fn bar() -> Bar<Headless> {
+    Bar::with_headless(1920, 1080).unwrap()
+}
+
+fn micros(d: Duration) -> u64 {
+    d.as_secs() * 1_000_000 + d.subsec_nanos() as u64 / 1_000
+}
+
+/// Run `f` for every round, and print how long a round takes.
+fn time<F: FnMut(Vec<FormatItem>)>(name: &str, mut f: F) {
+    let lines = lines();
+
+    let start = Instant::now();
+    for l in lines {
+        f(l);
+    }
+
+    println!("{:<36} {:>8} us/iter", name, micros(start.elapsed() / ROUNDS));
+}
+
+fn main() {
+    let mut bar = bar();
+    bar.update(lines().remove(0));
+
+    time("full redraw, shaping all text", |l| {
+        bar.clear_text_cache();
+        bar.set_fmt(l);
+        bar.draw();
+    });
+
+    time("full redraw, shaping the clock", |l| {
+        bar.set_fmt(l);
+        bar.draw();
+    });
+
+    time("update, shaping all text", |l| {
+        bar.clear_text_cache();
+        bar.update(l);
+    });
+
+    time("update, shaping the clock", |l| bar.update(l));
+}
@@ -1,27 +1,111 @@
 use cairo;
 use pango;
 
+use error::{Error, Result};
 use format;
 use window;
-use window::Dock;
+use window::{Dock, Edge, Event, Key, Modifiers, Popup, PopupEvent};
 
-use std::num::ParseIntError;
+use std::cell::{Cell, RefCell};
+use std::cmp;
+use std::collections::HashMap;
+use std::f64::consts::PI;
+use std::mem;
+use std::os::unix::io::RawFd;
 use std::str::FromStr;
 use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
 
-use cairo::XCBSurface;
 use pango::LayoutExt;
 use pangocairo::CairoContextExt;
 
 #[cfg(feature = "image")]
 use gdk::ContextExt;
 
+/// A click on a clickable area, given to the function set with
+/// `Bar::on_command`.
+#[derive(Clone, Debug)]
+pub struct Click<'a> {
+    /// The command of the area.
+    pub cmd:    &'a str,
+    pub button: u8,
+    pub mods:   Modifiers,
+
+    /// The position of the pointer, relative to the bar.
+    pub x:      i16,
+    pub y:      i16,
+
+    /// The bounds of the area along the bar, both included, i.e. along
+    /// y for vertical bars. Areas always span the whole bar across.
+    pub area:   (i16, i16),
+}
+
+/// How text is laid out on vertical bars.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum VerticalText {
+    /// The whole bar is drawn turned a quarter clockwise, so text reads
+    /// from top to bottom.
+    Rotated,
+
+    /// Characters are stacked on top of each other and kept upright, as
+    /// are images.
+    Stacked,
+}
+
+/// A region with a tooltip: its text and bounds along the bar.
+type Tip = (String, (i16, i16));
+
+/// A menu opened by a click on its region.
+#[derive(Clone)]
+struct OpenMenu {
+    menu:     format::Menu,
+    area:     (i16, i16), // bounds of the region along the bar
+    pos:      (i16, i16), // position of the click
+    mods:     Modifiers,
+    selected: Option<usize>,
+
+    // Size of the entries in logical pixels, measured when opened
+    width:    f64,
+    row:      f64,
+}
+
+/// The space around the text of popups, in logical pixels.
+const POPUP_PADDING: f64 = 4.0;
+
 pub struct Bar<T: Dock> {
     window: T,
     surface: cairo::Surface,
     fmt: Vec<format::FormatItem>,
-    cmds: Arc<Mutex<Vec<(u8, String, i16, i16)>>>, // (mbutton, cmd, minx, maxx)
+    cmds: Arc<Mutex<Vec<(u8, String, i16, i16)>>>, // (mbutton, cmd, min, max)
     size: (i32, i32),
+    edge: Edge,
+    vertical: Arc<AtomicBool>, // whether clicks are along y
+    vertical_text: VerticalText,
+    scale: f64, // pixels per logical pixel
+    fixed_scale: bool, // whether the scale was set with set_scale
+    tray_icons: Cell<usize>, // number of tray icons last drawn
+    tips: Arc<Mutex<Vec<Tip>>>,
+    hover: Arc<Mutex<Option<(Tip, Instant)>>>, // the tip under the pointer
+    tooltip: Option<Tip>, // the tip shown
+    tooltip_delay: Duration,
+    menus: Arc<Mutex<Vec<(format::Menu, (i16, i16))>>>,
+    menu_click: Arc<Mutex<Option<OpenMenu>>>, // menu to open
+    menu: Option<OpenMenu>, // the menu shown
+    hovers: Arc<Mutex<Vec<(usize, (i16, i16))>>>, // regions with a style
+    hovered: Arc<Mutex<Option<usize>>>, // the region under the pointer
+    drawn_hover: Cell<Option<usize>>, // the region drawn as hovered
+    stale: Cell<bool>, // whether the bar changed since the last full draw
+    areas: RefCell<Vec<(f64, f64)>>, // where the items were last drawn
+    fonts: RefCell<HashMap<String, pango::FontDescription>>,
+    layouts: RefCell<HashMap<(String, String), pango::Layout>>, // (text, font)
+    cursors: Arc<Mutex<Vec<(String, (i16, i16))>>>, // regions with a cursor
+    cursor: Arc<Mutex<Option<String>>>, // the cursor under the pointer
+    shown_cursor: Option<String>,
+    popup_style: (String, format::Colour, format::Colour), // font, fg, bg
+    geometry: Option<String>,
+    cmd_fn: Arc<Mutex<Box<Fn(&Click) + Sync + Send>>>,
+    event_fn: Arc<Mutex<Box<Fn(&Event, &[&str]) + Sync + Send>>>,
 }
 
 impl Bar<window::XCB> {
@@ -31,15 +115,53 @@ impl Bar<window::XCB> {
     /// By default, the width is set to the width of the screen and
     /// the height is 25. The bar is drawn at the top first, which can
     /// be set after.
-    pub fn with_xcb() -> Bar<window::XCB> {
-        let window = window::XCB::new();
-        window.dock();
+    ///
+    /// Fails if the X server cannot be reached, e.g. when `$DISPLAY` is
+    /// not set yet.
+    pub fn with_xcb() -> Result<Bar<window::XCB>> {
+        Bar::new(window::XCB::new()?)
+    }
+}
+
+impl Bar<window::Headless> {
+
+    /// Create an instance of Bar which is not displayed anywhere.
+    ///
+    /// The bar is drawn on a screen of size `w`x`h`, and the drawn
+    /// pixels can be read through `window()`. The defaults are the same
+    /// as for `with_xcb`.
+    pub fn with_headless(w: u16, h: u16) -> Result<Bar<window::Headless>> {
+        Bar::new(window::Headless::new(w, h))
+    }
+}
+
+#[cfg(feature = "wayland")]
+impl Bar<window::Wayland> {
+
+    /// Create an instance of Bar using the wlr-layer-shell protocol.
+    ///
+    /// This needs a compositor implementing the protocol, such as sway.
+    /// The defaults are the same as for `with_xcb`.
+    pub fn with_wayland() -> Result<Bar<window::Wayland>> {
+        Bar::new(window::Wayland::new()?)
+    }
+}
+
+impl<T: Dock> Bar<T> {
+
+    /// Create an instance of Bar drawing into `window`.
+    fn new(window: T) -> Result<Bar<T>> {
+        window.dock()?;
 
-        let surface =  window.create_surface();
+        let surface = window.create_surface()?;
         let fmt = Vec::new();
         let size = (1, 1);
         let cmds: Arc<Mutex<Vec<(u8, String, i16, i16)>>> =
             Arc::new(Mutex::new(Vec::new()));
+        let cmd_fn: Arc<Mutex<Box<Fn(&Click) + Sync + Send>>> =
+            Arc::new(Mutex::new(Box::new(|c| println!("{}", c.cmd))));
+        let event_fn: Arc<Mutex<Box<Fn(&Event, &[&str]) + Sync + Send>>> =
+            Arc::new(Mutex::new(Box::new(|_, _| {})));
 
         let mut r = Bar {
             window,
@@ -47,121 +169,1077 @@ impl Bar<window::XCB> {
             fmt,
             cmds,
             size,
+            edge: Edge::Top,
+            vertical: Arc::new(AtomicBool::new(false)),
+            vertical_text: VerticalText::Rotated,
+            scale: 1.0,
+            fixed_scale: false,
+            tray_icons: Cell::new(0),
+            tips: Arc::new(Mutex::new(Vec::new())),
+            hover: Arc::new(Mutex::new(None)),
+            tooltip: None,
+            tooltip_delay: Duration::from_millis(500),
+            menus: Arc::new(Mutex::new(Vec::new())),
+            menu_click: Arc::new(Mutex::new(None)),
+            menu: None,
+            hovers: Arc::new(Mutex::new(Vec::new())),
+            hovered: Arc::new(Mutex::new(None)),
+            drawn_hover: Cell::new(None),
+            stale: Cell::new(true),
+            areas: RefCell::new(Vec::new()),
+            fonts: RefCell::new(HashMap::new()),
+            layouts: RefCell::new(HashMap::new()),
+            cursors: Arc::new(Mutex::new(Vec::new())),
+            cursor: Arc::new(Mutex::new(None)),
+            shown_cursor: None,
+            popup_style: (String::new(),
+                          format::Colour::new(1.0, 1.0, 1.0, 1.0),
+                          format::Colour::new(0.0, 0.0, 0.0, 1.0)),
+            geometry: None,
+            cmd_fn,
+            event_fn,
         };
 
-        let width = r.window.get_screen_size().0 as i32;
-        r.set_size(width, 25);
+        r.scale = r.window.get_scale();
+        let (w, h) = r.full_size(r.px(25));
+        r.set_size(w, h)?;
 
         // Set callbacks
         let cmds = r.cmds.clone();
-        r.window.click_cb(move |x, _, b| {
+        let cmd_fn = r.cmd_fn.clone();
+        let event_fn = r.event_fn.clone();
+        let vertical = r.vertical.clone();
+        let tips = r.tips.clone();
+        let hover = r.hover.clone();
+        let menus = r.menus.clone();
+        let menu_click = r.menu_click.clone();
+        let hovers = r.hovers.clone();
+        let hovered = r.hovered.clone();
+        let cursors = r.cursors.clone();
+        let cursor = r.cursor.clone();
+        r.window.event_cb(move |e| {
             let cmds = cmds.lock().unwrap();
+            let (x, y) = e.pos();
+
+            // Position along the bar
+            let p = if vertical.load(Ordering::SeqCst) { y } else { x };
+
+            // Follow the tooltip under the pointer. Clicking hides it
+            // until the pointer rests again.
+            let mut hover = hover.lock().unwrap();
+            match e {
+                Event::Motion { .. } | Event::Enter { .. } => {
+                    let tip = tips.lock().unwrap().iter()
+                        .find(|&&(_, (l, r))| p >= l && p <= r)
+                        .cloned();
+
+                    let same = match (&*hover, &tip) {
+                        (&Some((ref a, _)), &Some(ref b)) => a == b,
+                        _ => false,
+                    };
+
+                    if !same {
+                        *hover = tip.map(|t| (t, Instant::now()));
+                    }
+                }
+
+                Event::Leave { .. } |
+                Event::Press { .. } |
+                Event::Scroll { .. } => *hover = None,
+
+                _ => {}
+            }
+
+            // Styles are redrawn once the events are handled
+            match e {
+                Event::Motion { .. } | Event::Enter { .. } => {
+                    *hovered.lock().unwrap() = hovers.lock().unwrap().iter()
+                        .find(|&&(_, (l, r))| p >= l && p <= r)
+                        .map(|&(region, _)| region);
+                }
+                Event::Leave { .. } => *hovered.lock().unwrap() = None,
+                _ => {}
+            }
+
+            // The cursor chosen for the region, else a hand over what
+            // can be clicked
+            match e {
+                Event::Motion { .. } | Event::Enter { .. } => {
+                    let chosen = cursors.lock().unwrap().iter()
+                        .find(|&&(_, (l, r))| p >= l && p <= r)
+                        .map(|&(ref c, _)| c.clone());
+
+                    let clickable = || {
+                        cmds.iter().any(|&(_, _, l, r)| p >= l && p <= r)
+                        || menus.lock().unwrap().iter()
+                               .any(|&(_, (l, r))| p >= l && p <= r)
+                    };
+
+                    *cursor.lock().unwrap() = match chosen {
+                        Some(c) => Some(c),
+                        None if clickable() => Some(String::from("pointer")),
+                        None => None,
+                    };
+                }
+                Event::Leave { .. } => *cursor.lock().unwrap() = None,
+                _ => {}
+            }
+
+            // Menus are opened once the events are handled
+            if let Event::Press { button, .. } = e {
+                let menu = menus.lock().unwrap().iter()
+                    .find(|&&(ref m, (l, r))| {
+                        m.button == button && p >= l && p <= r
+                    })
+                    .cloned();
 
-            for &(mb, ref s, xl, xr) in cmds.iter() {
-                if mb == b && x >= xl && x <= xr {
-                    println!("{}", s);
+                if let Some((menu, area)) = menu {
+                    *menu_click.lock().unwrap() = Some(OpenMenu {
+                        menu,
+                        area,
+                        pos:      (x, y),
+                        mods:     e.mods(),
+                        selected: None,
+                        width:    0.0,
+                        row:      0.0,
+                    });
+                }
+            }
+
+            // Commands of the areas under the pointer
+            let under: Vec<&str> = cmds.iter()
+                .filter(|&&(_, _, xl, xr)| p >= xl && p <= xr)
+                .map(|&(_, ref s, _, _)| s.as_str())
+                .collect();
+
+            event_fn.lock().unwrap()(&e, &under);
+
+            // Presses and scrolling trigger the commands of their button
+            let b = match e {
+                Event::Press { .. } | Event::Scroll { .. } => e.button(),
+                _ => None,
+            };
+
+            if let Some(b) = b {
+                let f = cmd_fn.lock().unwrap();
+
+                for &(mb, ref s, xl, xr) in cmds.iter() {
+                    if mb == b && p >= xl && p <= xr {
+                        f(&Click {
+                            cmd:    s,
+                            button: b,
+                            mods:   e.mods(),
+                            x,
+                            y,
+                            area:   (xl, xr),
+                        });
+                    }
                 }
             }
         });
 
-        return r;
+        Ok(r)
     }
-}
 
-impl<T: Dock> Bar<T> {
-    pub fn set_size(&mut self, w: i32, h: i32) {
-        assert!(w >= 0);
-        assert!(h >= 0);
+    /// Set the function called with every input event on the bar.
+    ///
+    /// It is given the event along with the commands of the clickable
+    /// areas under the pointer, which makes it possible to implement
+    /// hovering, dragging or modifier-clicks. Clicks still trigger the
+    /// function set with `on_command`.
+    pub fn on_event<F>(&mut self, f: F)
+        where F: Fn(&Event, &[&str]) + Send + Sync + 'static {
+
+        let mut cb = self.event_fn.lock().unwrap();
+        *cb = Box::new(f);
+    }
+
+    /// Get the window the bar is drawn in.
+    pub fn window(&self) -> &T {
+        &self.window
+    }
+
+    /// Get the window the bar is drawn in.
+    ///
+    /// Changing the size or position of the window directly will not
+    /// update the bar, so the methods of `Bar` should be used for that.
+    pub fn window_mut(&mut self) -> &mut T {
+        &mut self.window
+    }
+
+    /// Set the function called when a clickable area is clicked.
+    ///
+    /// By default, the command is printed to stdout, like lemonbar does.
+    pub fn on_command<F>(&mut self, f: F)
+        where F: Fn(&Click) + Send + Sync + 'static {
+
+        let mut cb = self.cmd_fn.lock().unwrap();
+        *cb = Box::new(f);
+    }
+
+    /// Set the size of the bar.
+    ///
+    /// Both dimensions must fit in a `u16`, else `Error::Geometry` is
+    /// returned.
+    pub fn set_size(&mut self, w: i32, h: i32) -> Result<()> {
+        if !fits_u16(w) || !fits_u16(h) {
+            return Err(Error::Geometry(format!("{}x{}", w, h)));
+        }
 
         self.size = (w, h);
-        self.window.set_size(w as u16, h as u16);
-        self.surface.set_size(w, h);
+        self.window.set_size(w as u16, h as u16)?;
+        self.surface = self.window.create_surface()?;
+        self.stale.set(true);
+
+        // Text is shaped for the scale and the direction of the bar
+        self.layouts.borrow_mut().clear();
+
+        Ok(())
     }
 
-    pub fn set_offset(&mut self, x: i32, y: i32) {
-        assert!(x >= 0);
-        assert!(y >= 0);
+    /// Set the offset of the bar from the corner of its output.
+    ///
+    /// Like for `set_size`, both values must fit in a `u16`.
+    pub fn set_offset(&mut self, x: i32, y: i32) -> Result<()> {
+        if !fits_u16(x) || !fits_u16(y) {
+            return Err(Error::Geometry(format!("+{}+{}", x, y)));
+        }
 
-        self.window.set_offset(x as u16, y as u16);
+        self.window.set_offset(x as u16, y as u16)
     }
 
     /// Set the bars geometry with WxH+x+y format.
     ///
-    /// This does not validate the string and might return Err. If the
-    /// width is omitted, it defaults to the screen width. The height
-    /// defaults to 25, for absolutely no reason.
+    /// Like in lemonbar, every part is optional, e.g. "x20" only sets
+    /// the height and "+10" only sets x. Returns `Error::Geometry` if the
+    /// string is invalid. The size along the edge of the bar defaults to
+    /// the size of the output. The size across defaults to 25, for
+    /// absolutely no reason.
     ///
-    /// The position arguments x and y both default to 0.
-    pub fn set_geometry(&mut self, g: &str) -> Result<(), ParseIntError> {
-        fn stoi(s: &str) -> Result<i32, ParseIntError> {
-            if s.len() == 0 { Ok(0) }
-            else { i32::from_str(s) }
+    /// The position arguments x and y both default to 0. Every value is
+    /// in logical pixels, so it is multiplied by the scale.
+    pub fn set_geometry(&mut self, g: &str) -> Result<()> {
+        let err = || Error::Geometry(String::from(g));
+
+        // Width, height, x and y, in the order they appear
+        let mut v: [Option<i32>; 4] = [None; 4];
+        let mut i = 0;
+
+        // The leading '=' of X geometry strings is optional
+        let s = if g.starts_with('=') { &g[1..] } else { g };
+        if s.is_empty() {
+            return Err(err());
         }
 
-        let (mut w, mut h) = (self.window.get_screen_size().0 as i32, 0);
-        let (mut x, mut y) = (0, 0);
+        let b = s.as_bytes();
+        let mut p = 0;
+        while p < b.len() {
+            // There are only 4 fields
+            if i >= 4 {
+                return Err(err());
+            }
 
-        let mut pl1 = g.len();
-        if let Some(i) = g.find('+')  {
-            pl1 = i;
+            match b[p] {
+                b'x' => {
+                    // The 'x' must come before any '+', and lemonbar
+                    // ignores the rest of the string otherwise.
+                    if i > 0 { break; }
 
-            if let Some(i) = g.rfind('+') {
-                x = stoi(&g[pl1+1..i])?;
-                y = stoi(&g[i+1..g.len()])?;
-            } else {
-                x = stoi(&g[pl1..g.len()])?;
+                    i = 1;
+                    p += 1;
+                }
+
+                b'+' => {
+                    // A '+' without a size before skips it
+                    i = if i < 1 { 2 } else { i + 1 };
+                    p += 1;
+                }
+
+                b'0'...b'9' => {
+                    let start = p;
+                    while p < b.len() && b[p].is_ascii_digit() {
+                        p += 1;
+                    }
+
+                    v[i] = Some(i32::from_str(&s[start..p])
+                                    .map_err(|_| err())?);
+                }
+
+                _ => return Err(err()),
+            }
+        }
+
+        let (dw, dh) = self.full_size(self.px(25));
+        let w = v[0].map(|v| self.px(v)).unwrap_or(dw);
+        let h = v[1].map(|v| self.px(v)).unwrap_or(dh);
+        let x = v[2].map(|v| self.px(v)).unwrap_or(0);
+        let y = v[3].map(|v| self.px(v)).unwrap_or(0);
+
+        self.set_size(w, h)?;
+        self.set_offset(x, y)?;
+        self.geometry = Some(String::from(g));
+
+        Ok(())
+    }
+
+    /// Dock the bar on the output with the given name.
+    ///
+    /// The length is reset to the size of the new output, so the
+    /// geometry should be set after this.
+    pub fn set_output(&mut self, name: &str) -> Result<()> {
+        self.window.set_output(name)?;
+
+        let (w, h) = self.full_size(self.thickness());
+        self.set_size(w, h)?;
+
+        self.update_scale()
+    }
+
+    /// Set the number of pixels per logical pixel, instead of following
+    /// the DPI of the output.
+    ///
+    /// The geometry is applied again, or the thickness is scaled.
+    pub fn set_scale(&mut self, scale: f64) -> Result<()> {
+        if !(scale > 0.0) {
+            return Err(Error::Geometry(format!("scale {}", scale)));
+        }
+
+        self.fixed_scale = true;
+        self.rescale(scale)
+    }
+
+    /// Get the number of pixels per logical pixel.
+    pub fn scale(&self) -> f64 {
+        self.scale
+    }
+
+    /// Follow the scale of the output, unless it was set with
+    /// `set_scale`.
+    fn update_scale(&mut self) -> Result<()> {
+        if self.fixed_scale {
+            return Ok(());
+        }
+
+        let scale = self.window.get_scale();
+        self.rescale(scale)
+    }
+
+    fn rescale(&mut self, scale: f64) -> Result<()> {
+        if scale == self.scale {
+            return Ok(());
+        }
+
+        let thickness = self.thickness() as f64 * scale / self.scale;
+        self.scale = scale;
+
+        match self.geometry.clone() {
+            Some(g) => self.set_geometry(&g),
+            None    => {
+                let (w, h) = self.full_size(thickness.round() as i32);
+                self.set_size(w, h)
             }
         }
+    }
+
+    /// Convert logical pixels to pixels.
+    fn px(&self, v: i32) -> i32 {
+        (v as f64 * self.scale).round() as i32
+    }
+
+    /// Set the name of the window, e.g. for window manager rules.
+    pub fn set_name(&mut self, name: &str) -> Result<()> {
+        self.window.set_name(name)
+    }
+
+    /// Set the instance and class names of the window, e.g. for window
+    /// manager rules or compositor exclusions.
+    pub fn set_class(&mut self, instance: &str, class: &str) -> Result<()> {
+        self.window.set_class(instance, class)
+    }
 
-        if let Some(i) = g.find('x') {
-            if let Some(s) = g.get(0..i) {
-                w = stoi(s)?;
+    /// Follow a change of the screen layout.
+    ///
+    /// The window is moved back onto its output, the geometry is applied
+    /// again to the new output size and the bar is redrawn. Returns false
+    /// if the output the bar was docked on has been disconnected, in which
+    /// case the bar should be dropped.
+    pub fn update_screen(&mut self) -> Result<bool> {
+        if !self.window.refresh_screen()? {
+            return Ok(false);
+        }
+
+        self.update_scale()?;
+
+        match self.geometry.clone() {
+            Some(g) => self.set_geometry(&g)?,
+            None    => {
+                let (w, h) = self.full_size(self.thickness());
+                self.set_size(w, h)?;
             }
+        }
+
+        self.draw();
+        Ok(true)
+    }
+
+    /// Set the function called when the screen layout changes.
+    ///
+    /// This is called from `handle_events`, so it should only take note
+    /// of the change. `update_screen` should be called once the events
+    /// have been handled.
+    pub fn on_screen_change<F>(&mut self, f: F)
+        where F: Fn() + Send + Sync + 'static {
+
+        self.window.screen_cb(f);
+    }
+
+    /// Get the file descriptor of the window's connection.
+    ///
+    /// `handle_events` should be called when it becomes readable, e.g.
+    /// after waiting on it with poll(2). It is negative if the window
+    /// never gets any events.
+    pub fn get_fd(&self) -> RawFd {
+        self.window.get_fd()
+    }
+
+    /// Handle the pending events of the window, without blocking.
+    ///
+    /// The callbacks set with `on_event`, `on_command` and
+    /// `on_screen_change` are called from here. Events may be queued
+    /// while waiting for replies from the display server, so this should
+    /// also be called before going to sleep, not only when the file
+    /// descriptor is readable.
+    ///
+    /// Returns `Error::Disconnected` once the connection to the display
+    /// server is lost. The bar cannot be used anymore after that, and a
+    /// new one has to be created to reconnect.
+    pub fn handle_events(&mut self) -> Result<()> {
+        self.window.handle_events()?;
+
+        // Some windows have no popups, and go without tooltips and menus
+        let clicked = self.menu_click.lock().unwrap().take();
+        let r = match clicked {
+            Some(m) => self.open_menu(m),
+            None    => self.handle_menu(),
+        };
+        let r = r.and_then(|_| self.update_tooltip());
 
-            if let Some(s) = g.get(i+1..pl1) {
-                h = stoi(s)?;
+        match r {
+            Err(Error::Unsupported(_)) | Ok(()) => {}
+            Err(e) => return Err(e),
+        }
+
+        // Make room for the tray icons which came or went
+        if self.window.tray_icons() != self.tray_icons.get() {
+            self.draw();
+        }
+
+        // Restyle the regions the pointer moved between
+        let hovered = *self.hovered.lock().unwrap();
+        let drawn = self.drawn_hover.get();
+        if hovered != drawn {
+            let areas: Vec<(i16, i16)> = self.hovers.lock().unwrap().iter()
+                .filter(|&&(r, _)| Some(r) == hovered || Some(r) == drawn)
+                .map(|&(_, area)| area)
+                .collect();
+
+            self.draw_parts(Some(&areas[..]));
+        }
+
+        self.update_cursor()
+    }
+
+    /// Show the cursor wanted under the pointer, if it changed.
+    ///
+    /// Cursors the window does not know fall back to the default one.
+    fn update_cursor(&mut self) -> Result<()> {
+        let cursor = self.cursor.lock().unwrap().clone();
+        if cursor == self.shown_cursor {
+            return Ok(());
+        }
+
+        match self.window.set_cursor(cursor.as_ref().map(|c| c.as_str())) {
+            Err(Error::Unsupported(_)) => self.window.set_cursor(None)?,
+            r => r?,
+        }
+
+        self.shown_cursor = cursor;
+        Ok(())
+    }
+
+    /// Set how long the pointer has to stay over a region before its
+    /// tooltip is shown. This is half a second by default.
+    pub fn set_tooltip_delay(&mut self, d: Duration) {
+        self.tooltip_delay = d;
+    }
+
+    /// Set the font and colours of tooltips and menus.
+    ///
+    /// By default, they use the default font of pango, in white on black.
+    /// The selected entry of menus has the colours swapped.
+    pub fn set_popup_style(&mut self, font: &str, fg: format::Colour,
+                           bg: format::Colour) {
+        self.popup_style = (String::from(font), fg, bg);
+    }
+
+    /// Get how long to wait before the pending tooltip is due, if the
+    /// pointer is over a region with a tooltip which is not shown yet.
+    ///
+    /// `update_tooltip` should be called then, which `handle_events`
+    /// does.
+    pub fn tooltip_timeout(&self) -> Option<Duration> {
+        let hover = self.hover.lock().unwrap();
+
+        match *hover {
+            Some((ref tip, since)) if self.tooltip.as_ref() != Some(tip) => {
+                let due = since + self.tooltip_delay;
+                let now = Instant::now();
+                Some(if due > now { due - now } else { Duration::new(0, 0) })
             }
-        } else {
-            w = stoi(&g[0..pl1])?;
+            _ => None,
+        }
+    }
+
+    /// Show the tooltip of the region under the pointer once it has been
+    /// there long enough, and hide it once the pointer is gone.
+    pub fn update_tooltip(&mut self) -> Result<()> {
+        // The popup is taken
+        if self.menu.is_some() {
+            return Ok(());
         }
 
-        self.set_size(w, h);
-        self.set_offset(x, y);
+        let want = match *self.hover.lock().unwrap() {
+            Some((ref tip, since)) => {
+                let shown = self.tooltip.as_ref() == Some(tip);
+                if shown || Instant::now() >= since + self.tooltip_delay {
+                    Some(tip.clone())
+                } else {
+                    None
+                }
+            }
+            None => None,
+        };
+
+        if want == self.tooltip {
+            return Ok(());
+        }
 
+        match want {
+            Some(ref tip) => self.show_tooltip(tip)?,
+            None          => self.window.close_popup(),
+        }
+
+        self.tooltip = want;
         Ok(())
     }
 
-    pub fn bottom(&mut self, b: bool) {
-        if b { self.window.bottom(); }
-        else { self.window.top();    }
+    /// Draw a tooltip in a popup, centred on its region.
+    fn show_tooltip(&mut self, tip: &Tip) -> Result<()> {
+        let &(ref text, area) = tip;
+        let s = self.scale;
+
+        let (w, h) = self.popup_layout(&cairo::Context::new(&self.surface),
+                                       text)
+                         .get_pixel_size();
+        let size = (((w as f64 + 2.0 * POPUP_PADDING) * s).ceil() as u16,
+                    ((h as f64 + 2.0 * POPUP_PADDING) * s).ceil() as u16);
+
+        let surface = self.window.open_popup(Popup::Tooltip, area, size)?;
+        {
+            let cr = self.popup_context(&surface);
+            let fg = &self.popup_style.1;
+
+            cr.set_source_rgba(fg.r, fg.g, fg.b, fg.a);
+            cr.move_to(POPUP_PADDING, POPUP_PADDING);
+            cr.show_pango_layout(&self.popup_layout(&cr, text));
+        }
+
+        self.window.flush_popup();
+        Ok(())
+    }
+
+    /// Lay `text` out in the font of popups.
+    fn popup_layout(&self, cr: &cairo::Context, text: &str) -> pango::Layout {
+        let layout = cr.create_pango_layout();
+        layout.set_font_description(&self.font(&self.popup_style.0));
+        layout.set_text(text);
+        layout
+    }
+
+    /// Get a context to draw on a popup in logical pixels, painted with
+    /// the background of popups.
+    fn popup_context(&self, surface: &cairo::Surface) -> cairo::Context {
+        let cr = cairo::Context::new(surface);
+        let bg = &self.popup_style.2;
+
+        cr.set_operator(cairo::Operator::Source);
+        cr.scale(self.scale, self.scale);
+        cr.set_source_rgba(bg.r, bg.g, bg.b, bg.a);
+        cr.paint();
+        cr.set_operator(cairo::Operator::Over);
+        cr
+    }
+
+    /// Open a menu next to its region, at least as wide as it.
+    fn open_menu(&mut self, mut m: OpenMenu) -> Result<()> {
+        // Tooltips would get in the way
+        *self.hover.lock().unwrap() = None;
+        self.tooltip = None;
+
+        let (mut w, mut h) = (0, 0);
+        {
+            let cr = cairo::Context::new(&self.surface);
+            for &(ref label, _) in &m.menu.items {
+                let (lw, lh) = self.popup_layout(&cr, label).get_pixel_size();
+                w = w.max(lw);
+                h = h.max(lh);
+            }
+        }
+
+        let area = (m.area.1 - m.area.0) as f64 / self.scale;
+        m.width = f64::max(w as f64 + 2.0 * POPUP_PADDING, area);
+        m.row = h as f64 + 2.0 * POPUP_PADDING;
+
+        self.menu = Some(m);
+        self.draw_menu()
+    }
+
+    /// Draw the open menu, with the selected entry in reverse colours.
+    fn draw_menu(&mut self) -> Result<()> {
+        let m = match self.menu {
+            Some(ref m) => m.clone(),
+            None        => return Ok(()),
+        };
+
+        let s = self.scale;
+        let size = ((m.width * s).ceil() as u16,
+                    (m.row * m.menu.items.len() as f64 * s).ceil() as u16);
+
+        let surface = match self.window.open_popup(Popup::Menu, m.area, size) {
+            Ok(surface) => surface,
+            Err(e)      => {
+                self.close_menu();
+                return Err(e);
+            }
+        };
+
+        {
+            let cr = self.popup_context(&surface);
+            let (_, ref fg, ref bg) = self.popup_style;
+
+            for (i, &(ref label, _)) in m.menu.items.iter().enumerate() {
+                let y = i as f64 * m.row;
+
+                let colour = if m.selected == Some(i) {
+                    cr.set_source_rgba(fg.r, fg.g, fg.b, fg.a);
+                    cr.rectangle(0.0, y, m.width, m.row);
+                    cr.fill();
+                    bg
+                } else {
+                    fg
+                };
+
+                cr.set_source_rgba(colour.r, colour.g, colour.b, colour.a);
+                cr.move_to(POPUP_PADDING, y + POPUP_PADDING);
+                cr.show_pango_layout(&self.popup_layout(&cr, label));
+            }
+        }
+
+        self.window.flush_popup();
+        Ok(())
+    }
+
+    fn close_menu(&mut self) {
+        self.menu = None;
+        self.window.close_popup();
+    }
+
+    /// Act on the input in the open menu.
+    ///
+    /// Entries are selected by hovering them or with the arrow keys and
+    /// the wheel, and chosen by clicking them or with Enter. Escape and
+    /// clicks elsewhere close the menu.
+    fn handle_menu(&mut self) -> Result<()> {
+        for e in self.window.popup_events() {
+            let (n, selected, width, row) = match self.menu {
+                Some(ref m) => (m.menu.items.len(), m.selected,
+                                m.width * self.scale, m.row * self.scale),
+                None => break,
+            };
+
+            // The entry at a position in the menu
+            let entry = |x: i16, y: i16| {
+                let (x, y) = (x as f64, y as f64);
+                if x >= 0.0 && x < width && y >= 0.0 && y < row * n as f64 {
+                    Some((y / row) as usize)
+                } else {
+                    None
+                }
+            };
+
+            let select = match e {
+                PopupEvent::Motion { x, y } => entry(x, y),
+
+                PopupEvent::Press { button: 4, .. }
+                | PopupEvent::Key(Key::Up) => {
+                    Some(selected.map_or(n - 1, |i| (i + n - 1) % n))
+                }
+
+                PopupEvent::Press { button: 5, .. }
+                | PopupEvent::Key(Key::Down) => {
+                    Some(selected.map_or(0, |i| (i + 1) % n))
+                }
+
+                PopupEvent::Press { x, y, .. } => {
+                    match entry(x, y) {
+                        Some(i) => self.choose(i),
+                        None    => self.close_menu(),
+                    }
+                    break;
+                }
+
+                PopupEvent::Key(Key::Enter) => {
+                    match selected {
+                        Some(i) => self.choose(i),
+                        None    => continue,
+                    }
+                    break;
+                }
+
+                PopupEvent::Key(Key::Escape) => {
+                    self.close_menu();
+                    break;
+                }
+            };
+
+            if select != selected {
+                if let Some(ref mut m) = self.menu {
+                    m.selected = select;
+                }
+                self.draw_menu()?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Run the command of an entry of the open menu, like a click on an
+    /// area would, and close the menu.
+    fn choose(&mut self, i: usize) {
+        let m = match self.menu.take() {
+            Some(m) => m,
+            None    => return,
+        };
+        self.window.close_popup();
+
+        let f = self.cmd_fn.lock().unwrap();
+        f(&Click {
+            cmd:    &m.menu.items[i].1,
+            button: m.menu.button,
+            mods:   m.mods,
+            x:      m.pos.0,
+            y:      m.pos.1,
+            area:   m.area,
+        });
+    }
+
+    /// Take the system tray of the screen, so that tray icons dock in the
+    /// bar where `%{Y}` is.
+    ///
+    /// Only one bar can have the tray, and this fails with
+    /// `Error::Unsupported` if another tray is running. Icons are laid out
+    /// along the bar, so this should be called after `set_edge`.
+    pub fn enable_tray(&mut self) -> Result<()> {
+        self.window.enable_tray(self.edge.is_vertical())
+    }
+
+    /// Get the length of the tray in logical pixels, icons being square.
+    fn tray_length(&self) -> f64 {
+        let icons = self.window.tray_icons() as f64;
+        icons * self.thickness() as f64 / self.scale
+    }
+
+    /// Get the outputs the bar can be docked on.
+    pub fn get_outputs(&self) -> Vec<window::Output> {
+        self.window.get_outputs()
+    }
+
+    /// Bypass the window manager, for those which do not support docks
+    /// and would decorate or tile the bar instead.
+    ///
+    /// The bar then keeps itself above the other windows, and still tells
+    /// the window manager which space it takes.
+    pub fn force_dock(&mut self, b: bool) -> Result<()> {
+        self.window.force_dock(b)
+    }
+
+    pub fn bottom(&mut self, b: bool) -> Result<()> {
+        self.set_edge(if b { Edge::Bottom } else { Edge::Top })
+    }
+
+    /// Dock the bar on an edge of its output.
+    ///
+    /// On the left and right, `%{l}`, `%{c}` and `%{r}` map to the top,
+    /// center and bottom of the bar, and clickable areas go along y.
+    /// When the bar turns, its geometry is applied again, or it spans the
+    /// whole output with the same thickness as before.
+    pub fn set_edge(&mut self, edge: Edge) -> Result<()> {
+        let thickness = self.thickness();
+        let turned = edge.is_vertical() != self.edge.is_vertical();
+
+        self.edge = edge;
+        self.vertical.store(edge.is_vertical(), Ordering::SeqCst);
+
+        if turned {
+            match self.geometry.clone() {
+                Some(g) => self.set_geometry(&g)?,
+                None    => {
+                    let (w, h) = self.full_size(thickness);
+                    self.set_size(w, h)?;
+                }
+            }
+        }
+
+        match edge {
+            Edge::Top    => self.window.top(),
+            Edge::Bottom => self.window.bottom(),
+            Edge::Left   => self.window.left(),
+            Edge::Right  => self.window.right(),
+        }
+    }
+
+    /// Set how text is laid out when the bar is vertical.
+    pub fn set_vertical_text(&mut self, v: VerticalText) {
+        self.vertical_text = v;
+        self.stale.set(true);
+        self.layouts.borrow_mut().clear();
+    }
+
+    /// Get the size of the bar across its edge.
+    fn thickness(&self) -> i32 {
+        if self.edge.is_vertical() { self.size.0 } else { self.size.1 }
+    }
+
+    /// Get the size of a bar spanning its whole output, with the given
+    /// thickness.
+    fn full_size(&self, thickness: i32) -> (i32, i32) {
+        let (w, h) = self.window.get_screen_size();
+
+        if self.edge.is_vertical() { (thickness, h as i32) }
+        else                       { (w as i32, thickness) }
+    }
+
+    /// Whether text is stacked rather than rotated.
+    fn stacked(&self) -> bool {
+        self.edge.is_vertical() && self.vertical_text == VerticalText::Stacked
+    }
+
+    /// Get the font described by `name`, parsing it the first time.
+    fn font(&self, name: &str) -> pango::FontDescription {
+        self.fonts.borrow_mut().entry(String::from(name))
+            .or_insert_with(|| pango::FontDescription::from_string(name))
+            .clone()
+    }
+
+    /// Get the layout of `t`, with its characters on top of each other
+    /// if text is stacked.
+    ///
+    /// Layouts are shaped on `cr` the first time, and kept for as long as
+    /// their text is shown. Kept ones are updated to the transformation
+    /// and font options of `cr`, which they would not follow otherwise.
+    fn text_layout(&self, cr: &cairo::Context, t: &format::Text)
+                   -> pango::Layout {
+        let key = (t.text.clone(), t.font.clone());
+        if let Some(layout) = self.layouts.borrow().get(&key) {
+            cr.update_pango_layout(layout);
+            return layout.clone();
+        }
+
+        let layout = cr.create_pango_layout();
+        layout.set_font_description(&self.font(&t.font));
+
+        if self.stacked() {
+            let chars: Vec<String> = t.text.chars()
+                                           .map(|c| c.to_string())
+                                           .collect();
+            layout.set_text(&chars.join("\n"));
+            layout.set_alignment(pango::Alignment::Center);
+        } else {
+            layout.set_text(&t.text);
+        }
+
+        self.layouts.borrow_mut().insert(key, layout.clone());
+        layout
+    }
+
+    /// Get the layout kept for `t`, if it was shown in the last draw.
+    pub fn cached_layout(&self, t: &format::Text) -> Option<pango::Layout> {
+        let key = (t.text.clone(), t.font.clone());
+        self.layouts.borrow().get(&key).cloned()
+    }
+
+    /// Forget the layouts and fonts kept across draws, so that the next
+    /// draw shapes all text again.
+    pub fn clear_text_cache(&self) {
+        self.layouts.borrow_mut().clear();
+        self.fonts.borrow_mut().clear();
     }
 
     pub fn set_fmt(&mut self, f: Vec<format::FormatItem>) {
         self.fmt = f;
+        self.stale.set(true);
     }
 
     pub fn draw(&self) {
+        self.draw_parts(None);
+        self.stale.set(false);
+    }
+
+    /// Replace the items with `f`, and draw only those which changed.
+    ///
+    /// Items are compared with those last drawn, by their look and their
+    /// place, so that a clock ticking only redraws and copies its digits.
+    /// Everything is drawn if the bar changed since, e.g. its size, or if
+    /// the tray moved.
+    pub fn update(&mut self, f: Vec<format::FormatItem>) {
+        if self.stale.get() {
+            self.fmt = f;
+            self.draw();
+            return;
+        }
+
+        let old_areas = self.areas.borrow().clone();
+        let old = mem::replace(&mut self.fmt, f);
+        let areas = self.item_areas(&self.bar_context());
+
+        let hovered = *self.hovered.lock().unwrap();
+        let drawn = self.drawn_hover.get();
+
+        // Items drawn the same way, at the same place
+        let same = |i: usize| match (old.get(i), self.fmt.get(i)) {
+            (Some(a), Some(b)) => {
+                a == b && old_areas[i] == areas[i]
+                && hover_style(a.bg(), drawn) == hover_style(b.bg(), hovered)
+            }
+            _ => false,
+        };
 
+        let changed: Vec<usize> = (0..cmp::max(old.len(), self.fmt.len()))
+            .filter(|&i| !same(i))
+            .collect();
 
+        // Tray icons are only placed by full draws
+        let is_tray = |v: Option<&format::FormatItem>| match v {
+            Some(&format::FormatItem::Tray(_)) => true,
+            _ => false,
+        };
+        if changed.iter().any(|&i| is_tray(old.get(i))
+                                   || is_tray(self.fmt.get(i))) {
+            self.draw();
+            return;
+        }
+
+        // Both where changed items were and where they are now
+        let s = self.scale;
+        let damage: Vec<(i16, i16)> = changed.iter()
+            .flat_map(|&i| old_areas.get(i).into_iter()
+                                    .chain(areas.get(i)))
+            .map(|&(pos, len)| {
+                ((pos * s).floor() as i16, ((pos + len) * s).ceil() as i16)
+            })
+            .filter(|&(l, r)| l < r)
+            .collect();
+
+        if damage.is_empty() {
+            return;
+        }
+
+        self.draw_parts(Some(&merge_areas(damage)));
+    }
+
+    /// Draw the items in some areas of the bar, or all of them.
+    ///
+    /// Areas are bounds along the bar in pixels, like those of clickable
+    /// areas. Only the items they cover are drawn again, but the regions
+    /// of all items are gathered anew.
+    fn draw_parts(&self, parts: Option<&[(i16, i16)]>) {
         let mut cmds = self.cmds.lock().unwrap();
-        *cmds = Vec::new();
+        let mut tips = self.tips.lock().unwrap();
+        let mut menus = self.menus.lock().unwrap();
+        let mut hovers = self.hovers.lock().unwrap();
+        let mut cursors = self.cursors.lock().unwrap();
+        cmds.clear();
+        tips.clear();
+        menus.clear();
+        hovers.clear();
+        cursors.clear();
 
-        let cr = cairo::Context::new(&self.surface);
+        let hovered = *self.hovered.lock().unwrap();
+        self.drawn_hover.set(hovered);
+
+        let cr = self.bar_context();
         cr.set_operator(cairo::Operator::Source);
 
-        let (bw, bh) = self.size;
-        let count = self.filler_count();
-        let inter = bw as f64 / count as f64;
-        let lengths = self.get_lengths();
-        let mut n = 0;
-        let mut pos = 0.0;
+        let s = self.scale;
+        let bh = self.thickness() as f64 / s;
+
+        if let Some(parts) = parts {
+            for &(l, r) in parts {
+                cr.rectangle(l as f64 / s, 0.0, (r - l) as f64 / s, bh);
+            }
+            cr.clip();
+        }
+
+        // Whether an item from `pos` of length `w` is to be drawn
+        let visible = |pos: f64, w: f64| {
+            parts.map_or(true, |parts| parts.iter().any(|&(l, r)| {
+                pos < r as f64 / s && pos + w > l as f64 / s
+            }))
+        };
 
-        // Take care of drawing background and adding commands
+        let stacked = self.stacked();
+        let areas = self.item_areas(&cr);
+        let mut tray = None;
+        if parts.is_none() {
+            self.tray_icons.set(self.window.tray_icons());
+        }
+
+        // Take care of drawing background and adding commands. Returns
+        // whether the rest of the item is to be drawn.
         let mut draw_bg = |bg: &format::BG, pos: f64, w: f64| {
 
+            // Areas are in pixels, like events
+            let (l, r) = ((pos * s) as i16, ((pos + w) * s) as i16);
+            for &(b, ref c) in &bg.cmd {
+                cmds.push((b, c.clone(), l, r));
+            }
+
+            if let Some(ref t) = bg.tip {
+                add_region(&mut tips, t, (l, r));
+            }
+            // Empty menus have nothing to open
+            if let Some(ref m) = bg.menu {
+                if !m.items.is_empty() {
+                    add_region(&mut menus, m, (l, r));
+                }
+            }
+            if let Some(ref h) = bg.hover {
+                add_region(&mut hovers, &h.region, (l, r));
+            }
+            if let Some(ref c) = bg.cursor {
+                add_region(&mut cursors, c, (l, r));
+            }
+
+            if !visible(pos, w) {
+                return false;
+            }
+
+            let style = hover_style(bg, hovered);
+
             // Text background
-            cr.set_source_rgba(bg.bg.r, bg.bg.g, bg.bg.b, bg.bg.a);
-            cr.rectangle(0.0, 0.0, w, bh as f64);
+            let c = style.and_then(|h| h.bg.as_ref()).unwrap_or(&bg.bg);
+            cr.set_source_rgba(c.r, c.g, c.b, c.a);
+            cr.rectangle(0.0, 0.0, w, bh);
             cr.fill();
 
             // Overline
@@ -172,83 +1250,207 @@ impl<T: Dock> Bar<T> {
             }
 
             // Underline
-            if let Some(ref ul) = bg.ul {
+            if let Some(ul) = style.and_then(|h| h.ul.as_ref())
+                                   .or(bg.ul.as_ref()) {
                 cr.set_source_rgba(ul.r, ul.g, ul.b, ul.a);
-                cr.rectangle(0.0, bh as f64 - bg.ol_size,
-                             w, bh as f64);
+                cr.rectangle(0.0, bh - bg.ul_size, w, bg.ul_size);
                 cr.fill();
             }
 
-            for &(b, ref s) in &bg.cmd {
-                cmds.push((b, s.clone(),
-                    pos as i16, pos as i16 + w as i16));
-            }
+            true
         };
 
-        for v in &self.fmt {
+        for (v, &(pos, len)) in self.fmt.iter().zip(&areas) {
+            cr.save();
+            cr.translate(pos, 0.0);
+
             match *v {
                 format::FormatItem::Text(ref t, ref bg) => {
+                    if draw_bg(bg, pos, len) {
+                        let layout = self.text_layout(&cr, t);
+                        let (w, h) = layout.get_pixel_size();
 
-                    // Set the font and text
-                    let font = pango::FontDescription::from_string(&t.font);
-                    let layout = cr.create_pango_layout();
-                    layout.set_font_description(&font);
-                    layout.set_text(&t.text);
-
-                    let (w, h) = layout.get_pixel_size();
+                        // Text foreground
+                        let fg = hover_style(bg, hovered)
+                            .and_then(|h| h.fg.as_ref())
+                            .unwrap_or(&t.fg);
+                        cr.set_source_rgba(fg.r, fg.g, fg.b, fg.a);
 
-                    draw_bg(bg, pos, w as f64);
+                        if stacked {
+                            // Back upright, from the top right corner of
+                            // the text
+                            cr.rotate(-PI / 2.0);
+                            cr.translate(-(bh + w as f64) / 2.0, 0.0);
+                        } else {
+                            let y = (bh - h as f64) / 2.0 + t.offset;
+                            cr.translate(0.0, y);
+                        }
 
-                    // Text foreground
-                    cr.save(); {
-                        cr.set_source_rgba(t.fg.r, t.fg.g, t.fg.b, t.fg.a);
-                        cr.translate(0.0, (bh - h) as f64 / 2.0);
                         cr.show_pango_layout(&layout);
-                    } cr.restore();
-
-                    // Move to next position
-                    cr.translate(w as f64, 0.0);
-                    pos += w as f64;
+                    }
                 }
 
                 #[cfg(feature = "image")]
                 format::FormatItem::Image(ref i, ref bg) => {
-                    let w = i.width as f64;
-                    let h = i.height as f64;
-                    let h_off = (bh - i.height) as f64 / 2.0;
+                    if draw_bg(bg, pos, len) {
+                        let w = i.width as f64 / i.scale;
+                        let h = i.height as f64 / i.scale;
+
+                        // Images are kept upright along with stacked text
+                        if stacked {
+                            cr.rotate(-PI / 2.0);
+                            cr.translate(-(bh + w) / 2.0, 0.0);
+                        } else {
+                            cr.translate(0.0, (bh - h) / 2.0);
+                        }
 
-                    draw_bg(bg, pos, w);
+                        // Back to the pixels of the image
+                        cr.scale(1.0 / i.scale, 1.0 / i.scale);
 
-                    cr.save(); {
-                        cr.set_source_pixbuf(i.pixbuf(), 0.0, h_off);
-                        cr.rectangle(0.0, h_off, w, h);
+                        cr.set_source_pixbuf(i.pixbuf(), 0.0, 0.0);
+                        cr.rectangle(0.0, 0.0, i.width as f64,
+                                     i.height as f64);
                         cr.set_operator(cairo::Operator::Over);
                         cr.fill();
-                    } cr.restore();
+                    }
+                }
 
-                    cr.translate(w, 0.0);
-                    pos += w;
+                format::FormatItem::Tray(ref bg) => {
+                    draw_bg(bg, pos, len);
+
+                    if tray.is_none() {
+                        tray = Some(pos);
+                    }
                 }
 
+                format::FormatItem::Offset(_, ref bg) |
                 format::FormatItem::Filler(ref bg) => {
+                    draw_bg(bg, pos, len);
+                }
+            }
+
+            cr.restore();
+        }
+
+        // Forget the layouts of text which is gone
+        self.layouts.borrow_mut().retain(|&(ref text, ref font), _| {
+            self.fmt.iter().any(|v| match *v {
+                format::FormatItem::Text(ref t, _) => {
+                    &t.text == text && &t.font == font
+                }
+                _ => false,
+            })
+        });
+        *self.areas.borrow_mut() = areas;
+
+        let parts = match parts {
+            Some(parts) => parts,
+            None        => {
+                // The icons draw themselves, once moved over their
+                // background
+                let size = self.thickness() as u16;
+                let tray = tray.map(|p| (p * s) as i16).map(|p| {
+                    if self.edge.is_vertical() { (0, p) } else { (p, 0) }
+                });
+                self.window.place_tray(tray, size, self.edge.is_vertical());
+
+                self.window.flush();
+                return;
+            }
+        };
+
+        for &(l, r) in parts {
+            let len = (r - l + 1) as u16;
+            if self.edge.is_vertical() {
+                self.window.flush_area((0, l), (self.size.0 as u16, len));
+            } else {
+                self.window.flush_area((l, 0), (len, self.size.1 as u16));
+            }
+        }
+    }
+
+    /// Create a context drawing on the bar.
+    ///
+    /// Vertical bars are drawn like horizontal ones turned a quarter
+    /// clockwise, and everything is in logical pixels, so that text, lines
+    /// and offsets follow the scale.
+    fn bar_context(&self) -> cairo::Context {
+        let cr = cairo::Context::new(&self.surface);
+
+        if self.edge.is_vertical() {
+            cr.translate(self.size.0 as f64, 0.0);
+            cr.rotate(PI / 2.0);
+        }
+
+        cr.scale(self.scale, self.scale);
+        cr
+    }
+
+    /// Get where each item goes along the bar, as its position and its
+    /// length in logical pixels, with text measured on `cr`.
+    fn item_areas(&self, cr: &cairo::Context) -> Vec<(f64, f64)> {
+        let mut r = Vec::with_capacity(self.fmt.len());
+
+        let bw = if self.edge.is_vertical() { self.size.1 }
+                 else                       { self.size.0 };
+        let lens = self.item_lengths(cr);
+        let lengths = self.get_lengths(&lens);
+        let count = self.filler_count();
+        let inter = bw as f64 / self.scale / count as f64;
+        let mut n = 0;
+        let mut pos = 0.0;
+
+        for (v, &len) in self.fmt.iter().zip(&lens) {
+            let len = match *v {
+                format::FormatItem::Filler(_) => {
                     n += 1;
-                    let mut pnext = (inter * n as f64) - pos;
+                    let pnext = (inter * n as f64) - pos;
 
                     if count == n {
-                        pnext -= lengths[n as usize];
+                        pnext - lengths[n as usize]
                     } else {
-                        pnext -= lengths[n as usize] / 2.0;
+                        pnext - lengths[n as usize] / 2.0
                     }
+                }
+
+                _ => len,
+            };
+
+            r.push((pos, len));
+            pos += len;
+        }
+
+        r
+    }
+
+    /// Get the length of each item, fillers being empty until placed.
+    fn item_lengths(&self, cr: &cairo::Context) -> Vec<f64> {
+        let stacked = self.stacked();
+        let mut tray = false; // only the first tray has icons
 
-                    draw_bg(bg, pos, pnext);
+        self.fmt.iter().map(|v| match *v {
+            format::FormatItem::Text(ref t, _) => {
+                let (w, h) = self.text_layout(cr, t).get_pixel_size();
+                (if stacked { h } else { w }) as f64
+            }
+
+            #[cfg(feature = "image")]
+            format::FormatItem::Image(ref i, _) => {
+                let len = if stacked { i.height } else { i.width };
+                len as f64 / i.scale
+            }
 
-                    cr.translate(pnext, 0.0);
-                    pos += pnext;
+            format::FormatItem::Offset(w, _) => w,
+
+            format::FormatItem::Tray(_) => {
+                if tray { 0.0 } else {
+                    tray = true;
+                    self.tray_length()
                 }
             }
-        }
 
-        self.window.flush();
+            format::FormatItem::Filler(_) => 0.0,
+        }).collect()
     }
 
     fn filler_count(&self) -> i32 {
@@ -263,37 +1465,77 @@ impl<T: Dock> Bar<T> {
         return r;
     }
 
-    fn get_lengths(&self) -> Vec<f64> {
+    /// Sum the lengths of the items between fillers.
+    fn get_lengths(&self, lens: &[f64]) -> Vec<f64> {
         let mut r: Vec<f64> = Vec::new(); // return val
 
-        let lay = cairo::Context::new(&self.surface);
-        let lay = lay.create_pango_layout();
-
         let mut n = 0.0;
-        for i in &self.fmt {
-            match *i {
-                format::FormatItem::Text(ref t, _) => {
-                    let font = pango::FontDescription::from_string(&t.font);
-                    lay.set_text(&t.text);
-                    lay.set_font_description(&font);
+        for (i, &len) in self.fmt.iter().zip(lens) {
+            if let &format::FormatItem::Filler(_) = i {
+                r.push(n);
+                n = 0.0;
+            } else {
+                n += len;
+            }
+        }
 
-                    let (w, _) = lay.get_pixel_size();
-                    n += w as f64;
-                }
+        r.push(n);
+        return r;
+    }
+}
 
-                #[cfg(feature = "image")]
-                format::FormatItem::Image(ref i, _) => {
-                    n += i.width as f64;
-                }
+fn fits_u16(v: i32) -> bool {
+    v >= 0 && v <= u16::max_value() as i32
+}
 
-                format::FormatItem::Filler(_) => {
-                    r.push(n);
-                    n = 0.0;
-                }
+/// Get the hover style of the items with `bg`, if the pointer is over
+/// their region.
+fn hover_style(bg: &format::BG, hovered: Option<usize>)
+               -> Option<&format::Hover> {
+    match bg.hover {
+        Some(ref h) if Some(h.region) == hovered => Some(h),
+        _ => None,
+    }
+}
+
+/// Sort areas along the bar, and join those which overlap or touch.
+fn merge_areas(mut areas: Vec<(i16, i16)>) -> Vec<(i16, i16)> {
+    areas.sort();
+
+    let mut r: Vec<(i16, i16)> = Vec::with_capacity(areas.len());
+    for (l, rr) in areas {
+        let joined = match r.last_mut() {
+            Some(last) if l <= last.1 => {
+                last.1 = cmp::max(last.1, rr);
+                true
             }
+            _ => false,
+        };
+
+        if !joined {
+            r.push((l, rr));
         }
+    }
 
-        r.push(n);
-        return r;
+    r
+}
+
+/// Add the region of `t` at `area` along the bar, extending the last one
+/// instead if it is the same and ends where this one starts.
+///
+/// This makes neighbours with the same tooltip, menu, hover style or
+/// cursor a single region.
+fn add_region<T>(regions: &mut Vec<(T, (i16, i16))>, t: &T,
+                 area: (i16, i16))
+    where T: Clone + PartialEq {
+    let extends = match regions.last() {
+        Some(&(ref lt, (_, lr))) => lt == t && lr == area.0,
+        None => false,
+    };
+
+    if extends {
+        (regions.last_mut().unwrap().1).1 = area.1;
+    } else {
+        regions.push((t.clone(), area));
     }
 }
@@ -0,0 +1,155 @@
+//! A small poll(2) based event loop, so that stdin, the connections to
+//! the display server and timers can all be handled on the main thread.
+
+use std::cmp;
+use std::io;
+use std::os::unix::io::RawFd;
+use std::time::{Duration, Instant};
+
+use libc;
+
+/// Waits for file descriptors to become readable or timers to expire.
+///
+/// Timers are identified by values of `T`, usually a small enum.
+pub struct EventLoop<T> {
+    timers: Vec<(T, Instant)>,
+}
+
+impl<T: Copy + PartialEq> EventLoop<T> {
+
+    pub fn new() -> EventLoop<T> {
+        EventLoop { timers: Vec::new() }
+    }
+
+    /// Make `timer` expire after `d`.
+    ///
+    /// If the timer is already set, it is postponed, which makes it easy
+    /// to wait for a burst of events to end.
+    pub fn set_timer(&mut self, timer: T, d: Duration) {
+        let deadline = Instant::now() + d;
+
+        match self.timers.iter().position(|&(t, _)| t == timer) {
+            Some(i) => self.timers[i].1 = deadline,
+            None    => self.timers.push((timer, deadline)),
+        }
+    }
+
+    /// Wait until one of `fds` is readable or a timer expires.
+    ///
+    /// Returns whether each of `fds` is readable, and the timers which
+    /// expired. Negative file descriptors are ignored, and if there is
+    /// nothing to wait for, this sleeps forever.
+    pub fn wait(&mut self, fds: &[RawFd]) -> io::Result<(Vec<bool>, Vec<T>)> {
+        let mut pfds: Vec<libc::pollfd> = fds.iter().map(|&fd| {
+            libc::pollfd { fd, events: libc::POLLIN, revents: 0 }
+        }).collect();
+
+        // Sleep until the nearest timer, rounding up to a millisecond
+        let now = Instant::now();
+        let timeout = match self.timers.iter().map(|&(_, i)| i).min() {
+            None    => -1,
+            Some(i) if i <= now => 0,
+            Some(i) => {
+                let d = i - now;
+                let ms = d.as_secs() * 1000
+                       + (d.subsec_nanos() as u64 + 999_999) / 1_000_000;
+                cmp::min(ms, i32::max_value() as u64) as i32
+            }
+        };
+
+        let n = unsafe {
+            libc::poll(pfds.as_mut_ptr(), pfds.len() as libc::nfds_t, timeout)
+        };
+
+        if n < 0 {
+            let e = io::Error::last_os_error();
+            if e.kind() != io::ErrorKind::Interrupted {
+                return Err(e);
+            }
+
+            // Interrupted by a signal, act as if nothing happened
+            for p in pfds.iter_mut() {
+                p.revents = 0;
+            }
+        }
+
+        let ready = pfds.iter().map(|p| {
+            p.revents & (libc::POLLIN | libc::POLLHUP | libc::POLLERR) != 0
+        }).collect();
+
+        let now = Instant::now();
+        let expired = self.timers.iter()
+                                 .filter(|&&(_, i)| i <= now)
+                                 .map(|&(t, _)| t)
+                                 .collect();
+        self.timers.retain(|&(_, i)| i > now);
+
+        Ok((ready, expired))
+    }
+}
+
+/// Reads lines from a file descriptor, without ever blocking once it is
+/// known to be readable.
+pub struct LineReader {
+    fd:  RawFd,
+    buf: Vec<u8>,
+    eof: bool,
+}
+
+impl LineReader {
+
+    pub fn new(fd: RawFd) -> LineReader {
+        LineReader { fd, buf: Vec::new(), eof: false }
+    }
+
+    /// Get the file descriptor to wait on. It is negative once the end of
+    /// the input has been reached, so that it is not polled anymore.
+    pub fn fd(&self) -> RawFd {
+        if self.eof { -1 } else { self.fd }
+    }
+
+    pub fn is_eof(&self) -> bool {
+        self.eof
+    }
+
+    /// Read once from the file descriptor and return the lines completed
+    /// by it, without their newline.
+    ///
+    /// This must only be called when the file descriptor is readable.
+    /// At the end of the input, a last line without newline is returned
+    /// too.
+    pub fn read_lines(&mut self) -> io::Result<Vec<String>> {
+        let mut chunk = [0u8; 4096];
+
+        let n = unsafe {
+            libc::read(self.fd, chunk.as_mut_ptr() as *mut libc::c_void,
+                       chunk.len())
+        };
+
+        if n < 0 {
+            let e = io::Error::last_os_error();
+            return match e.kind() {
+                io::ErrorKind::Interrupted
+                | io::ErrorKind::WouldBlock => Ok(Vec::new()),
+                _ => Err(e),
+            };
+        }
+
+        if n == 0 {
+            self.eof = true;
+            if !self.buf.is_empty() {
+                self.buf.push(b'\n');
+            }
+        } else {
+            self.buf.extend_from_slice(&chunk[..n as usize]);
+        }
+
+        let mut r = Vec::new();
+        while let Some(i) = self.buf.iter().position(|&c| c == b'\n') {
+            let line: Vec<u8> = self.buf.drain(..i + 1).collect();
+            r.push(String::from_utf8_lossy(&line[..i]).into_owned());
+        }
+
+        Ok(r)
+    }
+}
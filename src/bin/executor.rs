@@ -0,0 +1,82 @@
+//! Runs the commands of clicked areas directly, instead of printing them
+//! for a shell reading the output of the bar.
+
+use std::process::{Child, Command, Stdio};
+use std::sync::Mutex;
+
+use lemonade::Click;
+
+/// Runs commands with a shell, passing the details of the click in the
+/// environment:
+///
+/// - `LEMONADE_BUTTON`: the mouse button, numbered like in `%{A}`
+/// - `LEMONADE_X`, `LEMONADE_Y`: the pointer position in the bar
+/// - `LEMONADE_AREA_X`, `LEMONADE_AREA_WIDTH`: the bounds of the area
+/// - `LEMONADE_SHIFT`, `LEMONADE_CTRL`, `LEMONADE_ALT`, `LEMONADE_SUPER`:
+///   set to 1 for the modifiers held
+pub struct Executor {
+    shell:    String,
+    children: Mutex<Vec<Child>>,
+}
+
+impl Executor {
+
+    pub fn new(shell: &str) -> Executor {
+        Executor {
+            shell:    String::from(shell),
+            children: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Start the command of `click` without waiting for it.
+    ///
+    /// Errors are printed, as there is nobody to report them to.
+    pub fn run(&self, click: &Click) {
+        let mut cmd = Command::new(&self.shell);
+        cmd.arg("-c").arg(click.cmd)
+           .stdin(Stdio::null())
+           .env("LEMONADE_BUTTON", click.button.to_string())
+           .env("LEMONADE_X", click.x.to_string())
+           .env("LEMONADE_Y", click.y.to_string())
+           .env("LEMONADE_AREA_X", click.area.0.to_string())
+           .env("LEMONADE_AREA_WIDTH",
+                (click.area.1 - click.area.0 + 1).to_string());
+
+        let mods = [
+            ("LEMONADE_SHIFT", click.mods.shift),
+            ("LEMONADE_CTRL",  click.mods.ctrl),
+            ("LEMONADE_ALT",   click.mods.alt),
+            ("LEMONADE_SUPER", click.mods.logo),
+        ];
+
+        for &(var, held) in mods.iter() {
+            if held {
+                cmd.env(var, "1");
+            } else {
+                cmd.env_remove(var);
+            }
+        }
+
+        match cmd.spawn() {
+            Ok(child) => self.children.lock().unwrap().push(child),
+            Err(e)    => eprintln!("lemonade: Failed to run {}: {}",
+                                   self.shell, e),
+        }
+    }
+
+    /// Wait for the commands which exited, so that they do not linger as
+    /// zombies. Returns whether some are still running.
+    pub fn reap(&self) -> bool {
+        let mut children = self.children.lock().unwrap();
+        let mut i = 0;
+        while i < children.len() {
+            // Drop the child if its state cannot be known anymore
+            match children[i].try_wait() {
+                Ok(None) => i += 1,
+                _        => { children.remove(i); }
+            }
+        }
+
+        !children.is_empty()
+    }
+}
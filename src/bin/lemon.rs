@@ -1,8 +1,11 @@
 use std;
-use std::cell::RefCell;
+use std::cmp;
+use std::mem;
 use std::str::FromStr;
-use regex::Regex;
-use lemonade::format::{FormatItem, Image, Text, BG, Color};
+use lemonade::format::{FormatItem, Hover, Menu, Text, BG, Color};
+
+#[cfg(feature = "image")]
+use lemonade::format::Image;
 
 pub struct LemonParser {
     pub bg: Color,
@@ -12,24 +15,73 @@ pub struct LemonParser {
     pub ol_size: f64,
     pub ul_size: f64,
     pub font_list: Vec<String>,
-    re: Regex,
+    pub font_offsets: Vec<f64>, // vertical offsets, by font index
+    pub scale: f64, // pixels per logical pixel, for images
+}
+
+/// The state of the parser while it goes through a line. Like in
+/// lemonbar, colours, attributes and fonts carry over blocks and screens.
+struct State {
+    bg: Color,
+    fg: Color,
+    ol: Color,
+    ul: Color,
+    oline: bool, // overline
+    uline: bool, // underline
+    font: String,
+    offset: f64,
+
+    // Stack of the open clickable areas
+    butts: Vec<(u8, String)>,
+
+    // Stack of the open tooltips, the innermost one being shown
+    tips: Vec<String>,
+
+    // Stack of the open menus, the innermost one being opened
+    menus: Vec<Menu>,
+
+    // Stack of the open hover styles, the innermost one being used, and
+    // the number of regions with one so far
+    hovers: Vec<Hover>,
+    hover_regions: usize,
+
+    // Stack of the open cursor shapes, the innermost one being shown
+    cursors: Vec<String>,
+
+    // Items of each screen, by alignment
+    screens: Vec<Vec<Vec<FormatItem>>>,
+    screen: usize,
+    align: usize,
+
+    // Whether the line selects screens with %{S}
+    selected: bool,
+}
+
+impl State {
+
+    /// Generate a BG based on the current parameters
+    fn get_bg(&self, ol_size: f64, ul_size: f64) -> BG {
+        BG {
+            bg: self.bg.clone(),
+            ol: if self.oline { Some(self.ol.clone()) } else { None },
+            ul: if self.uline { Some(self.ul.clone()) } else { None },
+            ol_size,
+            ul_size,
+            cmd: self.butts.clone(),
+            tip: self.tips.last().cloned(),
+            menu: self.menus.last().cloned(),
+            hover: self.hovers.last().cloned(),
+            cursor: self.cursors.last().cloned(),
+        }
+    }
+
+    fn push(&mut self, item: FormatItem) {
+        self.screens[self.screen][self.align].push(item);
+    }
 }
 
 impl LemonParser {
     pub fn new() -> Self {
-        let re = Regex::new(concat!(
-            r"%\{(?P<type>",
-                "[lcr]", "|",
-                "[BFUu]", "#(?P<colo>-|(?:[[:xdigit:]]{3,4}){1,2})", "|",
-                "[!-+\\-]", "(?P<attr>[uo])", "|",
-                "T", "(?P<index>-|[1-9])", "|",
-                "A", "(?:(?P<butt>[1-9])?:(?P<cmd>(?:[^:]|\\\\:)+?):)?", "|",
-                "I", "(?P<height>\\d+):(?P<path>.+?)", "|",
-                "R",
-            r")\}",
-            )
-        ).unwrap();
-
         let bg = Color::new(0.0, 0.0, 0.0, 0.0);
         let fg = Color::new(1.0, 1.0, 1.0, 1.0);
         let ol = bg.clone();
@@ -37,6 +89,8 @@ impl LemonParser {
         let ol_size = 1.0;
         let ul_size = 1.0;
         let font_list = vec![String::new()];
+        let font_offsets = Vec::new();
+        let scale = 1.0;
 
         Self {
             bg,
@@ -46,242 +100,501 @@ impl LemonParser {
             ol_size,
             ul_size,
             font_list,
-            re,
+            font_offsets,
+            scale,
+        }
+    }
+
+    /// Get the vertical offset of the font at `index`.
+    ///
+    /// Like in lemonbar, fonts without an offset of their own use the
+    /// first one.
+    pub fn font_offset(&self, index: usize) -> f64 {
+        match self.font_offsets.get(index) {
+            Some(&o) => o,
+            None     => self.font_offsets.first().cloned().unwrap_or(0.0),
         }
     }
 
+    /// Parse a line for a single bar.
+    ///
+    /// `%{S}` cannot select another screen, so everything ends up on this
+    /// one.
     pub fn parse(&mut self, fmt: &str) -> Vec<FormatItem> {
-        // Temporary variables for computing string slices
-        let mut bpos: usize = 0;
-        let mut epos: usize = fmt.len();
-
-        // Colour storage
-        let bg = RefCell::new(self.bg.clone());
-        let fg = RefCell::new(self.fg.clone());
-        let ol = RefCell::new(self.ol.clone());
-        let ul = RefCell::new(self.ul.clone());
-
-        // Stack for holding buttons
-        let butts: RefCell<Vec<(u8, String)>> = RefCell::new(Vec::new());
-
-        // Attributes
-        let oline = RefCell::new(false); // overline
-        let uline = RefCell::new(false); // underline
-
-        let ol_size = self.ol_size;
-        let ul_size = self.ul_size;
-
-        // List of fonts and the current font
-        let font = RefCell::new(self.font_list[..].join(", "));
-
-        // Return vector
-        let mut v: Vec<Vec<FormatItem>> = Vec::with_capacity(3);
-        for _ in 0..3 { v.push(Vec::new()); }
-
-        // Index of currently processed vector
-        let mut i: usize = 0;
-
-        // Generate a BG based on the current parameters
-        let get_bg = || -> BG {
-            BG {
-                bg: bg.borrow().clone(),
-                ol: if *oline.borrow() { Some(ol.borrow().clone()) }
-                    else { None },
-                ul: if *uline.borrow() { Some(ul.borrow().clone()) }
-                    else { None },
-                ol_size,
-                ul_size,
-                cmd: butts.borrow().clone(),
-            }
+        self.parse_screens(fmt, 1).remove(0)
+    }
+
+    /// Parse a line for `n` bars, ordered like lemonbar orders monitors.
+    ///
+    /// As in lemonbar, `%{S}` sends what follows to another screen, and
+    /// the line starts on the first one. Lines which never use `%{S}` are
+    /// shown on every screen instead of the first one only.
+    pub fn parse_screens(&mut self, fmt: &str, n: usize)
+                         -> Vec<Vec<FormatItem>> {
+        let n = cmp::max(n, 1);
+
+        let mut st = State {
+            bg: self.bg.clone(),
+            fg: self.fg.clone(),
+            ol: self.ol.clone(),
+            ul: self.ul.clone(),
+            oline: false,
+            uline: false,
+            font: self.font_list[..].join(", "),
+            offset: self.font_offset(0),
+            butts: Vec::new(),
+            tips: Vec::new(),
+            menus: Vec::new(),
+            hovers: Vec::new(),
+            hover_regions: 0,
+            cursors: Vec::new(),
+            screens: (0..n).map(|_| vec![Vec::new(), Vec::new(), Vec::new()])
+                           .collect(),
+            screen: 0,
+            align: 0,
+            selected: false,
         };
 
-        let checkf = |v: &mut Vec<FormatItem>| {
-            if let Some(&FormatItem::Filler(_)) = v.last() {
-                v.pop();
+        let mut text = String::new();
+        let mut rest = fmt;
+
+        while let Some(i) = rest.find('%') {
+            text.push_str(&rest[..i]);
+            rest = &rest[i + 1..];
+
+            if rest.starts_with('{') {
+                match rest.find('}') {
+                    Some(end) => {
+                        self.push_text(&mut st, &text);
+                        text.clear();
+
+                        self.parse_block(&mut st, &rest[1..end]);
+                        rest = &rest[end + 1..];
+                    }
+
+                    // Like lemonbar, drop an unterminated "%{" and show
+                    // the rest as text
+                    None => rest = &rest[1..],
+                }
+            } else if let Some(c) = rest.chars().next() {
+                // '%' escapes any other character, "%%" being a '%'
+                text.push(c);
+                rest = &rest[c.len_utf8()..];
             }
+        }
+
+        text.push_str(rest);
+        self.push_text(&mut st, &text);
+
+        // The space between the alignments has the default background,
+        // without lines or clickable areas
+        let filler = BG {
+            bg: self.bg.clone(),
+            ol: None,
+            ul: None,
+            ol_size: self.ol_size,
+            ul_size: self.ul_size,
+            cmd: Vec::new(),
+            tip: None,
+            menu: None,
+            hover: None,
+            cursor: None,
         };
 
-        // Push s into the vector
-        let pusht = |v: &mut Vec<FormatItem>, s: &str| {
-            checkf(v);
+        let mut r: Vec<Vec<FormatItem>> = st.screens.into_iter().map(|s| {
+            let aligned = !s[1].is_empty() || !s[2].is_empty();
+            let mut v = Vec::new();
 
-            v.push(FormatItem::Text(
-                Text {
-                    fg: fg.borrow().clone(),
-                    text: String::from(s),
-                    font: font.borrow().clone(),
-                },
+            for (i, items) in s.into_iter().enumerate() {
+                if i > 0 && aligned {
+                    v.push(FormatItem::Filler(filler.clone()));
+                }
+                v.extend(items);
+            }
 
-                get_bg()
-            ));
-        };
+            v
+        }).collect();
 
-        // Push a filler into the vector
-        let pushf = |v: &mut Vec<FormatItem>| {
-            checkf(v);
-            v.push(FormatItem::Filler(get_bg()));
-        };
+        if !st.selected {
+            let first = r[0].clone();
+            for s in r.iter_mut().skip(1) {
+                *s = first.clone();
+            }
+        }
 
+        return r;
+    }
 
-        // Iterate through every formatting item
-        for mat in self.re.find_iter(fmt) {
-            let caps = self.re.captures(mat.as_str()).unwrap();
-            epos = mat.start();
+    fn push_text(&self, st: &mut State, s: &str) {
+        if s.is_empty() {
+            return;
+        }
 
-            pusht(&mut v[i], &fmt[bpos..epos]);
+        let item = FormatItem::Text(
+            Text {
+                fg: st.fg.clone(),
+                text: String::from(s),
+                font: st.font.clone(),
+                offset: st.offset,
+            },
 
-            let t = *&caps["type"].chars().nth(0).unwrap();
-            match t {
-                'l'|'c'|'r' => {
-                    let n = match t {
-                        'l' => 0,
-                        'c' => 1,
-                        'r' => 2,
-                        _   => 0,
-                    };
+            st.get_bg(self.ol_size, self.ul_size)
+        );
 
-                    // Check for repetition
-                    if i != n {
+        st.push(item);
+    }
 
-                        // We check if the last item isn't already a filler
-                        // and make sure not to push a filler onto the last
-                        // block
-                        match v[i].last() {
-                            Some(&FormatItem::Filler(_)) => {}
+    /// Apply the commands of a `%{...}` block, separated by spaces.
+    ///
+    /// As in lemonbar, an unknown command ends the block.
+    fn parse_block(&self, st: &mut State, block: &str) {
+        let mut rest = block;
 
-                            _ => if i != 2 {
-                                pushf(&mut v[i]);
-                            }
-                        }
+        loop {
+            rest = rest.trim_left();
 
-                        i = n;
-                    }
-                }
+            let t = match rest.chars().next() {
+                Some(t) => t,
+                None    => break,
+            };
+            rest = &rest[t.len_utf8()..];
 
-                'R' => {
-                    // Since fg and bg are wrapped in a RefCell, mem::swap
-                    // cannot be used. This is an alternative.
-                    unsafe {
-                        std::ptr::swap(fg.as_ptr(), bg.as_ptr());
-                    }
-                }
+            match t {
+                'l' => st.align = 0,
+                'c' => st.align = 1,
+                'r' => st.align = 2,
+
+                'R' => mem::swap(&mut st.fg, &mut st.bg),
 
                 'F'|'B'|'U'|'u' => {
-                    let def;
-                    let mut c = match t {
-                        'F' => { def = &self.fg; fg.borrow_mut() }
-                        'B' => { def = &self.bg; bg.borrow_mut() }
-                        'U' => { def = &self.ul; ul.borrow_mut() }
-                        'u' => { def = &self.ol; ol.borrow_mut() }
-                        _   => { panic!("") /* PLACEHOLDER */ }
+                    let (arg, r) = split_arg(rest);
+                    rest = r;
+
+                    let (c, def) = match t {
+                        'F' => (&mut st.fg, &self.fg),
+                        'B' => (&mut st.bg, &self.bg),
+                        'U' => (&mut st.ul, &self.ul),
+                        _   => (&mut st.ol, &self.ol),
                     };
 
-                    if &caps["colo"] == "-" {
+                    if arg == "-" {
                         *c = def.clone();
-                    } else {
-                        *c = Color::from_hex(&caps["colo"]).unwrap();
+                    } else if !arg.starts_with('#') {
+                        eprintln!("Invalid colour {}", arg);
+                    } else if let Ok(colo) = Color::from_hex(arg) {
+                        *c = colo;
                     }
                 }
 
                 'T' => {
-                    if &caps["index"] == "-" {
-                        *font.borrow_mut() = self.font_list[..].join(", ");
-                    } else {
-
-                        // 1-based indexing
-                        let i = usize::from_str(&caps["index"]).unwrap() - 1;
-
-                        if i > self.font_list.len() {
-                            eprintln!("Font index {} is too high", i);
-                                *font.borrow_mut() = self.font_list[..].join(", ");
-                        } else {
-                            *font.borrow_mut() = match self.font_list.get(i) {
-                                Some(f) => f.clone(),
-                                None    => String::new(),
+                    let (arg, r) = split_arg(rest);
+                    rest = r;
+
+                    // 1-based indexing. Like lemonbar, an index out of
+                    // range goes back to the fallback font list as "-"
+                    // does.
+                    let i = match arg {
+                        "-" => None,
+                        _   => match usize::from_str(arg) {
+                            Ok(i) if i >= 1 && i <= self.font_list.len() => {
+                                Some(i - 1)
+                            }
+                            Ok(_)  => None,
+                            Err(_) => {
+                                eprintln!("Invalid font index {}", arg);
+                                continue;
                             }
+                        },
+                    };
+
+                    match i {
+                        Some(i) => {
+                            st.font = self.font_list[i].clone();
+                            st.offset = self.font_offset(i);
+                        }
+                        None => {
+                            st.font = self.font_list[..].join(", ");
+                            st.offset = self.font_offset(0);
                         }
                     }
                 }
 
+                '!'|'+'|'-' => {
+                    let a = rest.chars().next();
+                    if let Some(a) = a {
+                        rest = &rest[a.len_utf8()..];
+                    }
+
+                    let attr = match a {
+                        Some('o') => &mut st.oline,
+                        Some('u') => &mut st.uline,
+                        _ => {
+                            eprintln!("Invalid attribute {:?}", a);
+                            continue;
+                        }
+                    };
+
+                    *attr = match t {
+                        '!' => !*attr,
+                        '+' => true,
+                        _   => false,
+                    };
+                }
+
                 'A' => {
-                    // This is to differentiate between
-                    // %{A} and %{Abut:cmd}. If caps is Some
-                    // then there is a command
-                    match caps.name("cmd") {
-
-                        // Push button on to the stack
-                        Some(_) => {
-                            // If no button is specified, chose 1
-                            let b = match caps.name("butt") {
-                                None    => 1,
-                                Some(s) => u8::from_str(s.as_str()).unwrap(),
-                            };
-                            // The command itself
-                            let c = String::from(&caps["cmd"]);
-                            butts.borrow_mut().push((b, c));
+                    let (b, r) = split_button(rest);
+                    rest = r;
+
+                    if !rest.starts_with(':') {
+                        // %{A} and %{A<button>} close the last area,
+                        // whatever its button
+                        if st.butts.pop().is_none() {
+                            eprintln!("Unassociated %{{A}}!");
                         }
+                        continue;
+                    }
+
+                    match split_cmd(&rest[1..]) {
+                        Some((c, r)) => {
+                            st.butts.push((b, c));
+                            rest = r;
+                        }
+                        None => {
+                            eprintln!("Unterminated command in %{{A}}");
+                            break;
+                        }
+                    }
+                }
+
+                // Tooltips open and close like clickable areas, and
+                // escape ':' the same way
+                'H' => {
+                    if !rest.starts_with(':') {
+                        if st.tips.pop().is_none() {
+                            eprintln!("Unassociated %{{H}}!");
+                        }
+                        continue;
+                    }
 
-                        // Pop off a button
+                    match split_cmd(&rest[1..]) {
+                        Some((t, r)) => {
+                            st.tips.push(t);
+                            rest = r;
+                        }
                         None => {
-                            if let None = butts.borrow_mut().pop() {
-                                eprintln!("Unassociated %{{A}}!");
+                            eprintln!("Unterminated tooltip in %{{H}}");
+                            break;
+                        }
+                    }
+                }
+
+                // Menus are a list of "<label>:<command>:" entries, up to
+                // the next command of the block, and close like areas
+                'M' => {
+                    let (button, r) = split_button(rest);
+                    rest = r;
+
+                    if !rest.starts_with(':') {
+                        if st.menus.pop().is_none() {
+                            eprintln!("Unassociated %{{M}}!");
+                        }
+                        continue;
+                    }
+
+                    let mut items = Vec::new();
+                    rest = &rest[1..];
+
+                    while !rest.is_empty() && !rest.starts_with(' ') {
+                        let item = split_cmd(rest).and_then(|(l, r)| {
+                            split_cmd(r).map(|(c, r)| ((l, c), r))
+                        });
+
+                        match item {
+                            Some((i, r)) => {
+                                items.push(i);
+                                rest = r;
                             }
+                            None => break,
                         }
                     }
+
+                    if items.is_empty()
+                        || (!rest.is_empty() && !rest.starts_with(' ')) {
+                        eprintln!("Unterminated menu in %{{M}}");
+                        break;
+                    }
+
+                    st.menus.push(Menu { button, items });
                 }
 
-                '!'|'+'|'-' => {
-                    let mut a = match &caps["attr"] {
-                        "o" => { oline.borrow_mut() }
-                        "u" => { uline.borrow_mut() }
-                        _   => { panic!("") }
-                    };
+                // Hover styles are "<fg>:<bg>:<underline>", any of which
+                // can be left empty, and close like areas
+                'V' => {
+                    let (arg, r) = split_arg(rest);
+                    rest = r;
 
-                    *a = match t {
-                        '!' => { ! *a  }
-                        '+' => { true  }
-                        '-' => { false }
-                        _   => { panic!("") }
+                    if arg.is_empty() {
+                        if st.hovers.pop().is_none() {
+                            eprintln!("Unassociated %{{V}}!");
+                        }
+                        continue;
+                    }
+
+                    let mut colours = Vec::new();
+                    for c in arg.splitn(3, ':') {
+                        colours.push(match c {
+                            "" | "-" => None,
+                            _ => match Color::from_hex(c) {
+                                Ok(c)  => Some(c),
+                                Err(_) => {
+                                    eprintln!("Invalid colour {}", c);
+                                    None
+                                }
+                            },
+                        });
+                    }
+                    colours.resize(3, None);
+
+                    st.hovers.push(Hover {
+                        region: st.hover_regions,
+                        fg:     colours[0].take(),
+                        bg:     colours[1].take(),
+                        ul:     colours[2].take(),
+                    });
+                    st.hover_regions += 1;
+                }
+
+                // Cursors are named like in X cursor themes, and close
+                // like areas
+                'C' => {
+                    let (arg, r) = split_arg(rest);
+                    rest = r;
+
+                    if !arg.is_empty() {
+                        st.cursors.push(String::from(arg));
+                    } else if st.cursors.pop().is_none() {
+                        eprintln!("Unassociated %{{C}}!");
+                    }
+                }
+
+                'O' => {
+                    let (arg, r) = split_arg(rest);
+                    rest = r;
+
+                    match u16::from_str(arg) {
+                        Ok(w) => {
+                            // Only the background is drawn, like lemonbar
+                            let mut bg = st.get_bg(self.ol_size,
+                                                   self.ul_size);
+                            bg.ol = None;
+                            bg.ul = None;
+
+                            st.push(FormatItem::Offset(w as f64, bg));
+                        }
+                        Err(_) => eprintln!("Invalid offset {}", arg),
+                    }
+                }
+
+                // Only the first one gets the icons
+                'Y' => {
+                    let bg = st.get_bg(self.ol_size, self.ul_size);
+                    st.push(FormatItem::Tray(bg));
+                }
+
+                'S' => {
+                    let last = st.screens.len() - 1;
+                    let s = rest.chars().next();
+                    if let Some(s) = s {
+                        rest = &rest[s.len_utf8()..];
+                    }
+
+                    st.screen = match s {
+                        Some('+') => cmp::min(st.screen + 1, last),
+                        Some('-') => st.screen.saturating_sub(1),
+                        Some('f') => 0,
+                        Some('l') => last,
+                        Some(d) if d.is_digit(10) => {
+                            cmp::min(d.to_digit(10).unwrap() as usize, last)
+                        }
+                        _ => {
+                            eprintln!("Invalid screen {:?}", s);
+                            st.screen
+                        }
                     };
+
+                    st.align = 0;
+                    st.selected = true;
                 }
 
+                // The path goes up to the end of the block. The height is
+                // in logical pixels, so the image is loaded at the scale
+                // of the bar to stay sharp.
                 #[cfg(feature = "image")]
                 'I' => {
-                    let path = &caps["path"];
-                    let height = i32::from_str(&caps["height"]).unwrap();
-                    let img = Image::from_file(path, -1, height);
+                    let scale = self.scale;
+                    let img = rest.find(':').and_then(|i| {
+                        i32::from_str(&rest[..i]).ok().map(|h| (h, i))
+                    }).map(|(h, i)| {
+                        let h = (h as f64 * scale).round() as i32;
+                        Image::from_file(&rest[i + 1..], -1, h).map(|mut img| {
+                            img.scale = scale;
+                            img
+                        })
+                    });
 
                     match img {
-                        Ok(img) => {
-                            checkf(&mut v[i]);
-                            v[i].push(FormatItem::Image(
-                                img,
-                                get_bg(),
-                            ));
+                        Some(Ok(img)) => {
+                            let bg = st.get_bg(self.ol_size, self.ul_size);
+                            st.push(FormatItem::Image(img, bg));
                         }
 
-                        Err(_) => {
+                        Some(Err(e)) => {
+                            eprintln!("{}", e);
                             std::process::exit(2);
                         }
+
+                        None => eprintln!("Invalid image {}", rest),
                     }
+
+                    break;
                 }
 
-                _ => {}
+                _ => break,
             }
-
-            bpos = mat.end();
         }
+    }
+}
 
-        if bpos > epos {
-            epos = fmt.len();
-        }
+/// Split the argument of a command from the rest of a block.
+fn split_arg(s: &str) -> (&str, &str) {
+    match s.find(char::is_whitespace) {
+        Some(i) => (&s[..i], &s[i..]),
+        None    => (s, ""),
+    }
+}
 
-        pusht(&mut v[i], &fmt[bpos..epos]);
+/// Split the button off the start of `%{A}` or `%{M}`, which defaults to
+/// the first one.
+fn split_button(s: &str) -> (u8, &str) {
+    match s.chars().next().and_then(|c| c.to_digit(10)) {
+        Some(d) if d > 0 => (d as u8, &s[1..]),
+        _                => (1, s),
+    }
+}
 
-        let mut r: Vec<FormatItem> = Vec::new();
-        for i in 0..3 {
-            r.extend_from_slice(&v[i]);
+/// Split the command of a clickable area at its closing ':', which can be
+/// escaped in it as "\:".
+fn split_cmd(s: &str) -> Option<(String, &str)> {
+    let b = s.as_bytes();
+    let mut i = 0;
+
+    while i < b.len() {
+        match b[i] {
+            b'\\' if b.get(i + 1) == Some(&b':') => i += 2,
+            b':' => return Some((s[..i].replace("\\:", ":"), &s[i + 1..])),
+            _    => i += 1,
         }
-        return r;
     }
+
+    None
 }
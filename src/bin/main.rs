@@ -1,98 +1,632 @@
 extern crate lemonade;
-extern crate regex;
+extern crate libc;
 #[macro_use]
 extern crate clap;
 
+mod event_loop;
+mod executor;
 mod lemon;
+use event_loop::{EventLoop, LineReader};
+use executor::Executor;
 use lemon::LemonParser;
 
-use std::io;
+use std::env;
 use std::str::FromStr;
-use lemonade::Bar;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+use clap::ArgMatches;
+use lemonade::{Bar, Error, VerticalText};
 use lemonade::format::Color;
+use lemonade::window::{Dock, Edge, Output};
 
 
+/// Timers of the main loop.
+#[derive(Clone, Copy, PartialEq)]
+enum Timer {
+    /// Screen changes come in bursts, so the bars are only updated
+    /// once things have settled down.
+    Screen,
+
+    /// Try to connect to the display server again.
+    Reconnect,
+
+    /// Wait for the commands started by the executor which exited.
+    Reap,
+
+    /// Show the tooltip under the pointer once it has rested there.
+    Tooltip,
+}
+
+/// How long to wait for more screen changes before updating the bars.
+const SCREEN_DELAY: u64 = 100; // ms
+
+/// How long to wait between attempts to reconnect.
+const RECONNECT_DELAY: u64 = 1000; // ms
+
+/// How often to check whether commands started by the executor exited.
+const REAP_DELAY: u64 = 1000; // ms
+
+/// Exit codes, so that scripts can tell what went wrong.
+mod exit {
+    /// Invalid arguments. clap uses this one too.
+    pub const USAGE: i32 = 1;
+    /// Invalid input, e.g. an image which cannot be loaded. This one is
+    /// used by the parser.
+    #[allow(dead_code)]
+    pub const INPUT: i32 = 2;
+    /// The display server cannot be reached.
+    pub const CONNECTION: i32 = 3;
+    /// The display server lacks something, or a request to it failed.
+    pub const DISPLAY: i32 = 4;
+    /// Reading stdin or waiting for events failed.
+    pub const IO: i32 = 5;
+    /// The connection to the display server was lost.
+    pub const DISCONNECTED: i32 = 6;
+}
+
+/// Print the error and exit with the matching exit code.
+fn fail(e: Error) -> ! {
+    eprintln!("lemonade: {}", e);
+
+    std::process::exit(match e {
+        Error::Connection(_)   => exit::CONNECTION,
+        Error::Disconnected(_) => exit::DISCONNECTED,
+        Error::Unsupported(_)
+        | Error::Request(_)    => exit::DISPLAY,
+        Error::NoOutput(_)
+        | Error::Geometry(_)
+        | Error::Colour(_)     => exit::USAGE,
+        Error::Image(_)        => exit::INPUT,
+        Error::Io(_)           => exit::IO,
+    });
+}
+
+/// The outputs bars should be created on.
+enum Outputs {
+    Default,
+    All,
+    Named(Vec<String>),
+}
+
+impl Outputs {
+
+    /// Get the outputs a bar should currently exist for. `None` stands
+    /// for the default output.
+    ///
+    /// This always returns at least one target, so there is always a bar
+    /// to receive screen changes on.
+    fn targets(&self, available: &[Output]) -> Vec<Option<String>> {
+        let r: Vec<Option<String>> = match *self {
+            Outputs::Default => vec![None],
+            Outputs::All     => available.iter()
+                                         .map(|o| Some(o.name.clone()))
+                                         .collect(),
+            Outputs::Named(ref v) => v.iter()
+                .filter(|n| available.iter().any(|o| &o.name == *n))
+                .map(|n| Some(n.clone()))
+                .collect(),
+        };
+
+        if r.is_empty() { vec![None] } else { r }
+    }
+}
+
+/// A constructor of `Bar`, such as `Bar::with_xcb`.
+type NewBar<T> = fn() -> lemonade::Result<Bar<T>>;
+
+/// State shared between the main loop and the callbacks of the bars.
+struct Shared {
+    /// Set by the bars when the screen layout changes
+    changed:  Arc<AtomicBool>,
+
+    /// Runs the commands of clicked areas, unless they are printed
+    executor: Option<Arc<Executor>>,
+}
+
+/// Create the bars on the outputs they should currently exist for.
+fn create_bars<T: Dock>(args: &ArgMatches, new_bar: NewBar<T>,
+                        outputs: &Outputs, shared: &Shared)
+                        -> Result<Vec<(Option<String>, Bar<T>)>, Error> {
+    let mut bars = Vec::new();
+
+    // Named outputs must exist
+    if let Outputs::Named(ref v) = *outputs {
+        for n in v {
+            let t = Some(n.clone());
+            let bar = create_bar(args, new_bar, &t, shared)?;
+            bars.push((t, bar));
+        }
+    } else {
+        let bar = create_bar(args, new_bar, &None, shared)?;
+        let available = bar.get_outputs();
+        bars.push((None, bar));
+
+        if let Outputs::All = *outputs {
+            bars.clear();
+            for t in outputs.targets(&available) {
+                let bar = create_bar(args, new_bar, &t, shared)?;
+                bars.push((t, bar));
+            }
+        }
+    }
+
+    dock_tray(args, &mut bars);
+    Ok(bars)
+}
+
+/// Put the system tray on the first bar, if asked to.
+///
+/// This is done again when bars come and go, so that the tray moves to
+/// another bar when its bar is dropped. Another tray may be running, so
+/// failing is not fatal.
+fn dock_tray<T: Dock>(args: &ArgMatches,
+                      bars: &mut [(Option<String>, Bar<T>)]) {
+    if !args.is_present("tray") {
+        return;
+    }
+
+    if let Some(&mut (_, ref mut bar)) = bars.first_mut() {
+        if let Err(e) = bar.enable_tray() {
+            eprintln!("lemonade: {}", e);
+        }
+    }
+}
+
+/// Create a bar on the given output and configure it from the arguments.
+fn create_bar<T: Dock>(args: &ArgMatches, new_bar: NewBar<T>,
+                       output: &Option<String>,
+                       shared: &Shared) -> Result<Bar<T>, Error> {
+    let mut bar = new_bar()?;
+
+    if let Some(ref s) = *output {
+        bar.set_output(s)?;
+    }
+
+    if let Some(s) = args.value_of("SCALE") {
+        bar.set_scale(f64::from_str(s).unwrap())?;
+    }
+
+    if args.is_present("FORCE") {
+        bar.force_dock(true)?;
+    }
+
+    // --edge wins over -b
+    let edge = match args.value_of("EDGE") {
+        Some("bottom") => Edge::Bottom,
+        Some("left")   => Edge::Left,
+        Some("right")  => Edge::Right,
+        Some(_)        => Edge::Top,
+        None if args.is_present("bott") => Edge::Bottom,
+        None           => Edge::Top,
+    };
+
+    if args.is_present("stacked") {
+        bar.set_vertical_text(VerticalText::Stacked);
+    }
+
+    bar.set_edge(edge)?;
+
+    if let Some(s) = args.value_of("GEOMETRY") {
+        bar.set_geometry(&s)?;
+    }
+
+    if let Some(s) = args.value_of("NAME") {
+        bar.set_name(&s)?;
+    }
+
+    // The instance name follows the window name, so that each bar can be
+    // told apart by both
+    if args.is_present("NAME") || args.is_present("CLASS") {
+        bar.set_class(args.value_of("NAME").unwrap_or("lemonade"),
+                      args.value_of("CLASS").unwrap_or("Lemonade"))?;
+    }
+
+    if let Some(s) = args.value_of("TIP_DELAY") {
+        bar.set_tooltip_delay(
+            Duration::from_millis(u64::from_str(s).unwrap()));
+    }
+
+    // Tooltips and menus look like the bar, but opaque
+    let font = args.values_of("FONT")
+        .map(|v| v.collect::<Vec<_>>().join(", "))
+        .unwrap_or_default();
+    let colour = |arg, default| {
+        args.value_of(arg)
+            .and_then(|s| Color::from_hex(s).ok())
+            .unwrap_or(default)
+    };
+    let mut bg = colour("BG_COLO", Color::new(0.0, 0.0, 0.0, 1.0));
+    bg.a = 1.0;
+    bar.set_popup_style(&font, colour("FG_COLO",
+                                      Color::new(1.0, 1.0, 1.0, 1.0)), bg);
+
+    let changed = shared.changed.clone();
+    bar.on_screen_change(move || {
+        changed.store(true, Ordering::SeqCst);
+    });
+
+    if let Some(ref executor) = shared.executor {
+        let executor = executor.clone();
+        bar.on_command(move |click| executor.run(click));
+    }
+
+    Ok(bar)
+}
+
 fn main() {
 
     let args = clap_app!(lemonade =>
         (about: "lemonbar replacement with extra features")
         (@arg GEOMETRY: -g +takes_value "Set geometry. Format is WxH+x+y")
         (@arg bott: -b "Dock bar at the bottom")
-        //(@arg FORCE: -d "Force docking on unsupported WMs")
-        (@arg FONT: -f +takes_value +multiple "Load a font")
-        //(@arg CLICK: -a +takes_value "Number of clickable areas")
+        (@arg EDGE: --edge +takes_value {is_edge}
+            "Dock bar on an edge: top, bottom, left or right")
+        (@arg stacked: --stacked
+            "Stack characters upright on vertical bars instead of \
+             rotating the text")
+        (@arg SCALE: --scale +takes_value {is_scale}
+            "Pixels per logical pixel, e.g. 2 on HiDPI screens. Defaults \
+             to Xft.dpi / 96, or to the DPI of the output")
+        (@arg tray: --tray
+            "Show the system tray where %{Y} is, on the first bar")
+        (@arg OUTPUT: --output +takes_value +multiple
+            "Dock a bar on each named output. Use 'all' for every output")
+        (@arg BACKEND: --backend +takes_value {is_backend}
+            "Display backend to use, either 'xcb' or 'wayland'")
+        (@arg FORCE: -d "Force docking on unsupported WMs")
+        (@arg FONT: -f +takes_value +multiple number_of_values(1)
+            "Load a font")
+        (@arg OFFSET: -o +takes_value +multiple number_of_values(1)
+            +allow_hyphen_values {is_offset}
+            "Vertical offset of the text in pixels, can be negative. \
+             The first one applies to the first font and to fonts without \
+             an offset, the next ones to the next fonts")
+        (@arg CLICK: -a +takes_value {is_size}
+            "Number of clickable areas. There is no limit, so this is \
+             only accepted for compatibility")
+        (@arg TIP_DELAY: --("tooltip-delay") +takes_value {is_size}
+            "Milliseconds to hover a region before its tooltip is shown. \
+             Defaults to 500")
         (@arg perm: -p "Don't exit after stdin stops")
-        //(@arg NAME: -n +takes_value "Set window name")
-        (@arg UL_SIZE: -u +takes_value "Underline width in pixels")
+        (@arg exec: --exec
+            "Run the commands of clicked areas instead of printing them")
+        (@arg SHELL: --shell +takes_value requires[exec]
+            "Shell used to run commands with --exec. Defaults to /bin/sh")
+        (@arg reconnect: --reconnect
+            "Reconnect when the connection to the display server is lost, \
+             instead of exiting")
+        (@arg NAME: -n +takes_value
+            "Set the window name, also used as instance name in WM_CLASS")
+        (@arg CLASS: --class +takes_value
+            "Set the class name in WM_CLASS. Defaults to 'Lemonade'")
+        (@arg UL_SIZE: -u +takes_value {is_size} "Underline width in pixels")
+        (@arg OL_SIZE: --olsize +takes_value {is_size}
+            "Overline width in pixels. Defaults to -u")
         (@arg BG_COLO: -B +takes_value {is_colo} "Set default background colour")
         (@arg FG_COLO: -F +takes_value {is_colo} "Set default foreground colour")
         (@arg UL_COLO: -U +takes_value {is_colo} "Set default underline colour")
-        (@arg OL_OLCO: -O +takes_value {is_colo} "Set default overline colour. \
+        (@arg OL_COLO: -O +takes_value {is_colo} "Set default overline colour. \
                                                   Defaults to -U")
     ).get_matches();
 
-    // bar takes care of drawing the window.
+    // Use wayland when running under a wayland compositor, unless the
+    // backend is chosen explicitly.
+    let backend = match args.value_of("BACKEND") {
+        Some(b) => b,
+        None    => {
+            if cfg!(feature = "wayland")
+                && env::var_os("WAYLAND_DISPLAY").is_some() {
+                "wayland"
+            } else {
+                "xcb"
+            }
+        }
+    };
+
+    match backend {
+        "wayland" => run_wayland(&args),
+        _         => run(&args, Bar::with_xcb),
+    }
+}
+
+#[cfg(feature = "wayland")]
+fn run_wayland(args: &ArgMatches) {
+    run(args, Bar::with_wayland);
+}
+
+#[cfg(not(feature = "wayland"))]
+fn run_wayland(_: &ArgMatches) {
+    eprintln!("lemonade was built without wayland support");
+    std::process::exit(exit::USAGE);
+}
+
+/// Create the bars and run the main loop.
+fn run<T: Dock>(args: &ArgMatches, new_bar: NewBar<T>) {
+
+    let executor = if args.is_present("exec") {
+        let shell = args.value_of("SHELL").unwrap_or("/bin/sh");
+        Some(Arc::new(Executor::new(shell)))
+    } else {
+        None
+    };
+
+    let shared = Shared {
+        changed: Arc::new(AtomicBool::new(false)),
+        executor,
+    };
+
+    let outputs = match args.values_of("OUTPUT") {
+        None    => Outputs::Default,
+        Some(v) => {
+            let v: Vec<String> = v.map(|s| s.to_string()).collect();
+            if v.iter().any(|s| s == "all") { Outputs::All }
+            else { Outputs::Named(v) }
+        }
+    };
+
+    // bars take care of drawing the windows, one per output.
     // lem handles the input.
-    let mut bar = Bar::with_xcb();
+    let mut bars = create_bars(args, new_bar, &outputs, &shared)
+        .unwrap_or_else(|e| fail(e));
     let mut lem = LemonParser::new();
 
     // Whether to exit when stdin ends
     let quit_on_input_end = ! args.is_present("perm");
 
-    bar.bottom(args.is_present("bott"));
+    // Whether to reconnect when the connection is lost. The bars are
+    // empty while reconnecting.
+    let reconnect = args.is_present("reconnect");
 
     // Set command-line arguments
-    if let Some(s) = args.value_of("GEOMETRY") {
-        bar.set_geometry(&s).unwrap();
-    }
-
     if let Some(s) = args.values_of("FONT") {
         lem.font_list = s.map(|s| s.to_string()).collect();
     }
 
+    if let Some(s) = args.values_of("OFFSET") {
+        lem.font_offsets = s.map(|s| f64::from_str(s).unwrap()).collect();
+    }
+
+    // Sizes and colours are validated by clap
     if let Some(s) = args.value_of("UL_SIZE") {
-        lem.ul_size = f64::from_str(s).unwrap(); // TODO: validate
-        if let None = args.value_of("OL_SIZE") {
-            lem.ol_size = f64::from_str(s).unwrap();
-        }
+        lem.ul_size = f64::from_str(s).unwrap();
+        lem.ol_size = lem.ul_size;
+    }
+
+    if let Some(s) = args.value_of("OL_SIZE") {
+        lem.ol_size = f64::from_str(s).unwrap();
     }
 
     if let Some(s) = args.value_of("BG_COLO") {
-        lem.bg = Color::from_hex(&s).unwrap();
+        lem.bg = Color::from_hex(&s).unwrap_or_else(|e| fail(e));
     }
 
     if let Some(s) = args.value_of("FG_COLO") {
-        lem.fg = Color::from_hex(&s).unwrap();
+        lem.fg = Color::from_hex(&s).unwrap_or_else(|e| fail(e));
     }
 
     if let Some(s) = args.value_of("OL_COLO") {
-        lem.ol = Color::from_hex(&s).unwrap();
+        lem.ol = Color::from_hex(&s).unwrap_or_else(|e| fail(e));
     }
 
     if let Some(s) = args.value_of("UL_COLO") {
-        lem.ul = Color::from_hex(&s).unwrap();
+        lem.ul = Color::from_hex(&s).unwrap_or_else(|e| fail(e));
+
+        if !args.is_present("OL_COLO") {
+            lem.ol = lem.ul.clone();
+        }
     }
 
-    // Loop for reading from stdin and variables
-    let mut buf = String::new();
-    loop {
-        match io::stdin().read_line(&mut buf) {
-            Ok(n) if n == 0 => {
-                match quit_on_input_end {
-                    true  => std::process::exit(0),
-                    false => continue,
+    let mut stdin = LineReader::new(libc::STDIN_FILENO);
+    let mut ev = EventLoop::new();
+
+    // The last line of input, used for bars created later on
+    let mut line = String::new();
+
+    'events: loop {
+        let mut result = Ok(());
+        for &mut (_, ref mut bar) in bars.iter_mut() {
+            result = result.and_then(|_| bar.handle_events());
+        }
+
+        match result {
+            Ok(()) => {}
+            Err(Error::Disconnected(ref s)) if reconnect => {
+                disconnected(s, &mut bars, &mut ev);
+            }
+            Err(e) => fail(e),
+        }
+
+        // Wake up when a tooltip is due
+        let tooltip = bars.iter()
+            .filter_map(|&(_, ref bar)| bar.tooltip_timeout())
+            .min();
+        if let Some(d) = tooltip {
+            ev.set_timer(Timer::Tooltip, d);
+        }
+
+        if shared.changed.swap(false, Ordering::SeqCst) {
+            ev.set_timer(Timer::Screen, Duration::from_millis(SCREEN_DELAY));
+        }
+
+        // Check for exited commands until they are all gone
+        if let Some(ref executor) = shared.executor {
+            if executor.reap() {
+                ev.set_timer(Timer::Reap, Duration::from_millis(REAP_DELAY));
+            }
+        }
+
+        // Once stdin is closed, there is nothing left to do but to wait
+        // for events on the bars.
+        let mut fds = vec![stdin.fd()];
+        fds.extend(bars.iter().map(|&(_, ref bar)| bar.get_fd()));
+
+        let (ready, timers) = match ev.wait(&fds) {
+            Ok(r)  => r,
+            Err(e) => fail(Error::Io(e)),
+        };
+
+        if ready[0] {
+            let lines = match stdin.read_lines() {
+                Ok(l)  => l,
+                Err(e) => fail(Error::Io(e)),
+            };
+
+            // Only the last line needs to be shown
+            if let Some(l) = lines.last() {
+                line = l.clone();
+                show(&mut lem, &line, &mut bars);
+            }
+
+            if stdin.is_eof() && quit_on_input_end {
+                std::process::exit(0);
+            }
+        }
+
+        if timers.contains(&Timer::Reconnect) {
+            match create_bars(args, new_bar, &outputs, &shared) {
+                Ok(b) => {
+                    bars = b;
+                    show(&mut lem, &line, &mut bars);
+                }
+
+                // The display server is not back yet
+                Err(Error::Connection(_))
+                | Err(Error::Disconnected(_)) => {
+                    ev.set_timer(Timer::Reconnect,
+                                 Duration::from_millis(RECONNECT_DELAY));
                 }
+
+                Err(e) => fail(e),
             }
-            Ok(_) => {}
-            Err(_) => std::process::exit(1),
         }
 
-        buf.pop(); // Remove newline
+        if timers.contains(&Timer::Screen) && !bars.is_empty() {
+            let available = bars[0].1.get_outputs();
+            let targets = outputs.targets(&available);
+
+            // Drop bars whose output is gone, update the others
+            bars.retain(|&(ref t, _)| targets.contains(t));
+            let mut i = 0;
+            while i < bars.len() {
+                match bars[i].1.update_screen() {
+                    Ok(true)  => i += 1,
+                    Ok(false) => { bars.remove(i); }
+                    Err(Error::Disconnected(ref s)) if reconnect => {
+                        disconnected(s, &mut bars, &mut ev);
+                        continue 'events;
+                    }
+                    Err(e)    => fail(e),
+                }
+            }
+
+            // Create bars on new outputs
+            for t in targets {
+                if bars.iter().any(|&(ref b, _)| *b == t) {
+                    continue;
+                }
+
+                // The output may be gone again already, or the display
+                // server with it
+                match create_bar(args, new_bar, &t, &shared) {
+                    Ok(bar) => bars.push((t, bar)),
+                    Err(Error::Connection(ref s))
+                    | Err(Error::Disconnected(ref s)) if reconnect => {
+                        disconnected(s, &mut bars, &mut ev);
+                        continue 'events;
+                    }
+                    Err(e)  => eprintln!("lemonade: {}", e),
+                }
+            }
+
+            if bars.is_empty() {
+                eprintln!("lemonade: No output left to dock on");
+                std::process::exit(exit::DISPLAY);
+            }
+
+            dock_tray(args, &mut bars);
+
+            // The screens selected by the input may have moved
+            show(&mut lem, &line, &mut bars);
+        }
+    }
+}
+
+/// Drop the bars after losing the connection to the display server, and
+/// try to connect again in a while.
+fn disconnected<T: Dock>(e: &str,
+                         bars: &mut Vec<(Option<String>, Bar<T>)>,
+                         ev: &mut EventLoop<Timer>) {
+    eprintln!("lemonade: Lost the connection to the display server ({}), \
+               reconnecting", e);
+
+    bars.clear();
+    ev.set_timer(Timer::Reconnect, Duration::from_millis(RECONNECT_DELAY));
+}
+
+/// Draw a line of input on the bars.
+///
+/// Like lemonbar orders monitors for `%{S}`, the bars are ordered by the
+/// position of their output, from left to right and then top to bottom.
+fn show<T: Dock>(lem: &mut LemonParser, line: &str,
+                 bars: &mut [(Option<String>, Bar<T>)]) {
+    if bars.is_empty() {
+        return;
+    }
+
+    let available = bars[0].1.get_outputs();
+    let position = |t: &Option<String>| {
+        t.as_ref()
+         .and_then(|n| available.iter().find(|o| &o.name == n))
+         .map(|o| (o.x, o.y))
+         .unwrap_or((0, 0))
+    };
+
+    let mut order: Vec<usize> = (0..bars.len()).collect();
+    order.sort_by_key(|&i| position(&bars[i].0));
+
+    // Images are loaded for the densest output, so they stay sharp on
+    // every bar
+    lem.scale = bars.iter().map(|&(_, ref b)| b.scale()).fold(0.0, f64::max);
 
-        bar.set_fmt(lem.parse(&buf));
-        bar.draw();
+    let screens = lem.parse_screens(line, bars.len());
+    for (&i, fmt) in order.iter().zip(screens) {
+        bars[i].1.update(fmt);
+    }
+}
+
+fn is_backend(s: String) -> Result<(), String> {
+    match s.as_str() {
+        "xcb"|"wayland" => Ok(()),
+        _ => Err("Backend must be either 'xcb' or 'wayland'".to_string()),
+    }
+}
+
+fn is_edge(s: String) -> Result<(), String> {
+    match s.as_str() {
+        "top"|"bottom"|"left"|"right" => Ok(()),
+        _ => Err("Edge must be top, bottom, left or right".to_string()),
+    }
+}
+
+fn is_scale(s: String) -> Result<(), String> {
+    match f64::from_str(&s) {
+        Ok(v) if v > 0.0 => Ok(()),
+        _ => Err("Must be a positive number".to_string()),
+    }
+}
+
+fn is_size(s: String) -> Result<(), String> {
+    match u16::from_str(&s) {
+        Ok(_)  => Ok(()),
+        Err(_) => Err("Must be a non-negative integer".to_string()),
+    }
+}
 
-        buf.clear();
+fn is_offset(s: String) -> Result<(), String> {
+    match i16::from_str(&s) {
+        Ok(_)  => Ok(()),
+        Err(_) => Err("Must be a number".to_string()),
     }
 }
 
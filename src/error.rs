@@ -0,0 +1,87 @@
+use std::error;
+use std::fmt;
+use std::io;
+use std::result;
+
+use xcb;
+
+/// The errors which can happen while creating or using a bar.
+#[derive(Debug)]
+pub enum Error {
+    /// Connecting to the display server failed.
+    Connection(String),
+
+    /// The connection to the display server was lost, e.g. because the
+    /// server exited. The window cannot be used anymore.
+    Disconnected(String),
+
+    /// The display server lacks something the bar needs, such as a
+    /// protocol extension or a suitable visual.
+    Unsupported(String),
+
+    /// A request to the display server failed.
+    Request(String),
+
+    /// There is no output with the given name.
+    NoOutput(String),
+
+    /// A geometry, size or offset is invalid.
+    Geometry(String),
+
+    /// A colour string is invalid.
+    Colour(String),
+
+    /// An image could not be loaded.
+    Image(String),
+
+    Io(io::Error),
+}
+
+pub type Result<T> = result::Result<T, Error>;
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Connection(ref s) =>
+                write!(f, "Failed to connect to the display server: {}", s),
+            Error::Disconnected(ref s) =>
+                write!(f, "Lost the connection to the display server: {}", s),
+            Error::Unsupported(ref s) =>
+                write!(f, "Unsupported display server: {}", s),
+            Error::Request(ref s) =>
+                write!(f, "Request to the display server failed: {}", s),
+            Error::NoOutput(ref s) => write!(f, "No such output: {}", s),
+            Error::Geometry(ref s) => write!(f, "Invalid geometry: {}", s),
+            Error::Colour(ref s)   => write!(f, "Invalid colour: {}", s),
+            Error::Image(ref s)    => write!(f, "Failed to load image: {}", s),
+            Error::Io(ref e)       => write!(f, "{}", e),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(error::Error + 'static)> {
+        match *self {
+            Error::Io(ref e) => Some(e),
+            _                => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Error {
+        Error::Io(e)
+    }
+}
+
+impl From<xcb::ConnError> for Error {
+    fn from(e: xcb::ConnError) -> Error {
+        Error::Connection(e.to_string())
+    }
+}
+
+impl From<xcb::GenericError> for Error {
+    fn from(e: xcb::GenericError) -> Error {
+        Error::Request(format!("X error code {}", e.error_code()))
+    }
+}
@@ -1,10 +1,12 @@
 #[cfg(feature = "image")]
 use gdk_pixbuf::Pixbuf;
 
+use error::{Error, Result};
+
 // Fucking fight me
 pub type Color = Colour;
 
-#[derive(Clone)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct Colour {
     pub r: f64,
     pub g: f64,
@@ -19,7 +21,7 @@ impl Colour {
 
     // Takes either: #rrggbb, #aarrggbb, #rbg, #argb
     // The '#' in front is not necessary
-    pub fn from_hex(hex: &str) -> Result<Self, &'static str>{
+    pub fn from_hex(hex: &str) -> Result<Self> {
         let mut s = String::from(hex);
         let mut c = [1.0, 0.0, 0.0, 0.0]; // argb
 
@@ -37,14 +39,15 @@ impl Colour {
             4 => { m = 1; b = 0 }
             6 => { m = 2; b = 1 }
             8 => { m = 2; b = 0 }
-            _ => return Err("Provided string as invalid length"),
+            _ => return Err(Error::Colour(String::from(hex))),
         }
 
         let mut it = s.chars().peekable();
         let mut n = 0;
         while it.peek().is_some() {
             let h: String = it.by_ref().take(m).collect();
-            let v = i32::from_str_radix(&h, 16).unwrap();
+            let v = i32::from_str_radix(&h, 16)
+                .map_err(|_| Error::Colour(String::from(hex)))?;
             c[b + n] = v as f64 / (16.0_f64.powi(m as i32) - 1.0);
             n += 1;
         }
@@ -58,7 +61,7 @@ impl Colour {
     }
 }
 
-#[derive(Clone)]
+#[derive(Clone, PartialEq)]
 pub struct BG {
     pub bg: Colour,
     pub ol: Option<Colour>,
@@ -66,13 +69,49 @@ pub struct BG {
     pub ol_size: f64,
     pub ul_size: f64,
     pub cmd: Vec<(u8, String)>,
+
+    /// The text of the tooltip shown when hovering, from `%{H}`.
+    pub tip: Option<String>,
+
+    /// The menu opened by clicking, from `%{M}`.
+    pub menu: Option<Menu>,
+
+    /// The style while the pointer is over the region, from `%{V}`.
+    pub hover: Option<Hover>,
+
+    /// The name of the cursor shown over the region, from `%{C}`.
+    pub cursor: Option<String>,
 }
 
-#[derive(Clone)]
+/// Colours replacing those of a region while the pointer is over it.
+/// Those which are not set are kept.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Hover {
+    /// Tells regions apart, so that neighbours with the same style are
+    /// highlighted separately.
+    pub region: usize,
+
+    pub fg: Option<Colour>,
+    pub bg: Option<Colour>,
+    pub ul: Option<Colour>,
+}
+
+/// A menu of commands, shown in a popup when its region is clicked.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Menu {
+    /// The button opening the menu.
+    pub button: u8,
+
+    /// The entries, as their label and their command.
+    pub items: Vec<(String, String)>,
+}
+
+#[derive(Clone, PartialEq)]
 pub struct Text {
     pub fg: Colour,
     pub text: String,
     pub font: String,
+    pub offset: f64, // vertical offset, positive is down
 }
 
 #[cfg(feature = "image")]
@@ -81,6 +120,11 @@ pub struct Image {
     pub path: String,
     pub width: i32,
     pub height: i32,
+
+    /// The number of pixels of the image per logical pixel, for images
+    /// loaded at the scale of the bar. The image is drawn at its width
+    /// and height divided by this.
+    pub scale: f64,
     img: Pixbuf,
 }
 
@@ -99,16 +143,10 @@ impl Image {
     /// according to height to preserver aspect ratio, and vice versa for
     /// the height.
     ///
-    /// If the file fails to load, an error is printed to stderr and an
-    /// emtpy image with a size of 0 is returned.
-    pub fn from_file(path: &str, w: i32, h: i32) -> Result<Self, ()> {
-        let img = match Pixbuf::new_from_file_at_size(path, w, h) {
-            Ok(img) => img,
-            Err(e)  => {
-                eprintln!("{}", e);
-                return Err(());
-            }
-        };
+    /// Fails with `Error::Image` if the file cannot be loaded.
+    pub fn from_file(path: &str, w: i32, h: i32) -> Result<Self> {
+        let img = Pixbuf::new_from_file_at_size(path, w, h)
+            .map_err(|e| Error::Image(format!("{}: {}", path, e)))?;
 
         let path   = String::from(path);
         let width  = img.get_width();
@@ -118,6 +156,7 @@ impl Image {
             path,
             width,
             height,
+            scale: 1.0,
             img,
         })
     }
@@ -127,11 +166,45 @@ impl Image {
     }
 }
 
-#[derive(Clone)]
+/// Images are the same if they were loaded from the same file at the same
+/// size, as their pixels are not compared.
+#[cfg(feature = "image")]
+impl PartialEq for Image {
+    fn eq(&self, other: &Image) -> bool {
+        self.path == other.path
+            && self.width == other.width
+            && self.height == other.height
+            && self.scale == other.scale
+    }
+}
+
+#[derive(Clone, PartialEq)]
 pub enum FormatItem {
     Text(Text, BG),
     Filler(BG),
 
+    /// Empty space of the given width, from `%{O}`.
+    Offset(f64, BG),
+
+    /// Space for the icons of the system tray, from `%{Y}`.
+    Tray(BG),
+
     #[cfg(feature = "image")]
     Image(Image, BG),
 }
+
+impl FormatItem {
+
+    /// Get the background of the item, with its regions.
+    pub fn bg(&self) -> &BG {
+        match *self {
+            FormatItem::Text(_, ref bg) |
+            FormatItem::Filler(ref bg) |
+            FormatItem::Offset(_, ref bg) |
+            FormatItem::Tray(ref bg) => bg,
+
+            #[cfg(feature = "image")]
+            FormatItem::Image(_, ref bg) => bg,
+        }
+    }
+}
@@ -1,6 +1,7 @@
 extern crate pango;
 extern crate cairo;
 extern crate cairo_sys;
+extern crate libc;
 extern crate pangocairo;
 extern crate xcb;
 
@@ -10,8 +11,22 @@ extern crate gdk;
 #[cfg(feature = "image")]
 extern crate gdk_pixbuf;
 
+#[cfg(feature = "wayland")]
+extern crate memmap;
+
+#[cfg(feature = "wayland")]
+extern crate tempfile;
+
+#[cfg(feature = "wayland")]
+extern crate wayland_client;
+
+#[cfg(feature = "wayland")]
+extern crate wayland_protocols;
+
 pub mod bar;
+pub mod error;
 pub mod format;
 pub mod window;
 
-pub use bar::Bar;
+pub use bar::{Bar, Click, VerticalText};
+pub use error::{Error, Result};
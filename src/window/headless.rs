@@ -0,0 +1,394 @@
+use std::cell::Cell;
+use std::mem;
+use std::os::unix::io::RawFd;
+use std::slice;
+use error::{Error, Result};
+use window::{create_image, place, popup_position, tray_positions, Dock,
+             Edge, Event, Modifiers, Output, Popup, PopupEvent, Struts};
+
+use cairo;
+use cairo_sys;
+
+/// A window which is not shown anywhere.
+///
+/// The bar is drawn into an image surface, which can be read back with
+/// `get_pixels`. Input can be simulated with `send_event` and `click`.
+/// This makes it possible to use a `Bar` without a display, e.g. in tests.
+pub struct Headless {
+    image:    cairo::Surface,
+
+    size:     (u16, u16), // (w, h)
+    pos:      (i16, i16), // (x, y)
+    offset:   (i16, i16), // (x, y) relative to the output
+    outputs:  Vec<Output>,
+    output:   Output,
+    edge:     Edge,
+    name:     String,
+    class:    (String, String), // (instance, class)
+    cursor:   Option<String>,
+    tray:     Option<usize>, // number of icons, if the tray is enabled
+    tray_area: Cell<Option<((i16, i16), u16, bool)>>,
+    popup:    Option<((i16, i16), (u16, u16))>, // position and size
+    popup_kind: Popup,
+    popup_image: cairo::Surface,
+    popup_events: Vec<PopupEvent>,
+    flushed:  Cell<Option<((i16, i16), (u16, u16))>>, // last area flushed
+
+    event_fn:  Box<Fn(Event) + Sync + Send>,
+    screen_fn: Box<Fn() + Sync + Send>,
+}
+
+impl Headless {
+
+    /// Create a headless window on a screen of the given size.
+    ///
+    /// The screen has a single output covering all of it, named
+    /// "headless".
+    pub fn new(w: u16, h: u16) -> Headless {
+        let output = Output {
+            name:    String::from("headless"),
+            x:       0,
+            y:       0,
+            w,
+            h,
+            mm_w:    0,
+            mm_h:    0,
+            primary: true,
+        };
+
+        Headless {
+            image:     create_image(1, 1),
+            size:      (1, 1),
+            pos:       (0, 0),
+            offset:    (0, 0),
+            outputs:   vec![output.clone()],
+            output,
+            edge:      Edge::Top,
+            name:      String::from("lemonade"),
+            class:     (String::from("lemonade"), String::from("Lemonade")),
+            cursor:    None,
+            tray:      None,
+            tray_area: Cell::new(None),
+            popup:     None,
+            popup_kind: Popup::Tooltip,
+            popup_image: create_image(1, 1),
+            popup_events: Vec::new(),
+            flushed:   Cell::new(None),
+            event_fn:  Box::new(|_| {}), // Placeholder closure
+            screen_fn: Box::new(|| {}),
+        }
+    }
+
+    /// Replace the outputs of the screen, as if monitors were plugged in
+    /// or out.
+    ///
+    /// The function set with `screen_cb` is called, like the other
+    /// backends do when the screen layout changes.
+    pub fn set_outputs(&mut self, outputs: Vec<Output>) {
+        self.outputs = outputs;
+
+        (self.screen_fn)();
+    }
+
+    /// Simulate an input event, as if it came from the display server.
+    pub fn send_event(&self, e: Event) {
+        (self.event_fn)(e);
+    }
+
+    /// Simulate a click of button `b` at (`x`, `y`) in the window.
+    ///
+    /// This sends a press and a release without any modifiers.
+    pub fn click(&self, x: i16, y: i16, b: u8) {
+        let mods = Modifiers::default();
+
+        self.send_event(Event::Press   { x, y, button: b, mods, time: 0 });
+        self.send_event(Event::Release { x, y, button: b, mods, time: 0 });
+    }
+
+    /// Get the position of the window on the screen.
+    pub fn get_position(&self) -> (i16, i16) {
+        self.pos
+    }
+
+    /// Get the size of the window.
+    pub fn get_size(&self) -> (u16, u16) {
+        self.size
+    }
+
+    /// Get the struts the window would publish, on a screen spanning all
+    /// the outputs like the X root window does.
+    pub fn get_struts(&self) -> Struts {
+        let w = self.outputs.iter()
+                            .map(|o| o.x as i32 + o.w as i32)
+                            .max().unwrap_or(0);
+        let h = self.outputs.iter()
+                            .map(|o| o.y as i32 + o.h as i32)
+                            .max().unwrap_or(0);
+
+        Struts::new(self.pos, self.size, (w as u16, h as u16), self.edge)
+    }
+
+    /// Get the name set with `set_name`.
+    pub fn get_name(&self) -> &str {
+        &self.name
+    }
+
+    /// Get the instance and class names set with `set_class`.
+    pub fn get_class(&self) -> (&str, &str) {
+        (&self.class.0, &self.class.1)
+    }
+
+    /// Get the name of the cursor shown over the window, if it is not the
+    /// default one.
+    pub fn get_cursor(&self) -> Option<&str> {
+        self.cursor.as_ref().map(|s| s.as_str())
+    }
+
+    /// Set the number of icons in the tray, as if they docked or left.
+    ///
+    /// This enables the tray if it is not.
+    pub fn set_tray_icons(&mut self, n: usize) {
+        self.tray = Some(n);
+    }
+
+    /// Get the position of each icon of the tray in the window, and the
+    /// size of icons. There are none while the tray is hidden.
+    pub fn get_tray_icons(&self) -> (Vec<(i16, i16)>, u16) {
+        match self.tray_area.get() {
+            Some((pos, size, vertical)) => {
+                (tray_positions(pos, size, vertical, self.tray_icons()), size)
+            }
+            None => (Vec::new(), 0),
+        }
+    }
+
+    /// Simulate an input event in the open menu.
+    pub fn send_popup_event(&mut self, e: PopupEvent) {
+        self.popup_events.push(e);
+    }
+
+    /// Get the position on the screen and the size of the popup, if it
+    /// is open.
+    pub fn get_popup(&self) -> Option<((i16, i16), (u16, u16))> {
+        self.popup
+    }
+
+    /// Get the position and size of the area shown by the last flush,
+    /// which is all of the window unless only part of it was redrawn.
+    pub fn get_flushed(&self) -> Option<((i16, i16), (u16, u16))> {
+        self.flushed.get()
+    }
+
+    /// Get the kind of the popup, if it is open.
+    pub fn get_popup_kind(&self) -> Option<Popup> {
+        self.popup.map(|_| self.popup_kind)
+    }
+
+    /// Get the pixels drawn in the window, row by row.
+    ///
+    /// The pixels are in cairo's ARGB32 format, i.e. with premultiplied
+    /// alpha, and the alpha channel in the upper 8 bits.
+    pub fn get_pixels(&self) -> Vec<u32> {
+        let (w, h) = (self.size.0 as usize, self.size.1 as usize);
+        let mut r = Vec::with_capacity(w * h);
+
+        unsafe {
+            let img = self.image.to_raw_none();
+            cairo_sys::cairo_surface_flush(img);
+
+            let data = cairo_sys::cairo_image_surface_get_data(img);
+            let stride = cairo_sys::cairo_image_surface_get_stride(img);
+
+            // Empty and broken surfaces have no data
+            if data.is_null() || w == 0 {
+                return r;
+            }
+
+            for y in 0..h {
+                let row = data.offset(y as isize * stride as isize);
+                let row = slice::from_raw_parts(row as *const u32, w);
+                r.extend_from_slice(row);
+            }
+        }
+
+        return r;
+    }
+
+    /// Get the pixel at (`x`, `y`) in the same format as `get_pixels`.
+    pub fn get_pixel(&self, x: u16, y: u16) -> u32 {
+        assert!(x < self.size.0 && y < self.size.1);
+
+        self.get_pixels()[y as usize * self.size.0 as usize + x as usize]
+    }
+
+    fn reposition_window(&mut self) {
+        self.pos = place(&self.output, self.offset, self.size, self.edge);
+    }
+}
+
+impl Dock for Headless {
+    fn create_surface(&self) -> Result<cairo::Surface> {
+        Ok(self.image.clone())
+    }
+
+    fn dock(&self) -> Result<()> {
+        Ok(())
+    }
+
+    fn force_dock(&mut self, _: bool) -> Result<()> {
+        Ok(())
+    }
+
+    fn top(&mut self) -> Result<()> {
+        self.edge = Edge::Top;
+        self.reposition_window();
+        Ok(())
+    }
+
+    fn bottom(&mut self) -> Result<()> {
+        self.edge = Edge::Bottom;
+        self.reposition_window();
+        Ok(())
+    }
+
+    fn left(&mut self) -> Result<()> {
+        self.edge = Edge::Left;
+        self.reposition_window();
+        Ok(())
+    }
+
+    fn right(&mut self) -> Result<()> {
+        self.edge = Edge::Right;
+        self.reposition_window();
+        Ok(())
+    }
+
+    fn set_size(&mut self, w: u16, h: u16) -> Result<()> {
+        self.size = (w, h);
+        self.image = create_image(w as i32, h as i32);
+        self.reposition_window();
+        Ok(())
+    }
+
+    fn set_offset(&mut self, x: u16, y: u16) -> Result<()> {
+        self.offset = (x as i16, y as i16);
+        self.reposition_window();
+        Ok(())
+    }
+
+    fn get_screen_size(&self) -> (u16, u16) {
+        (self.output.w, self.output.h)
+    }
+
+    /// Get the scale of the output from its physical size.
+    fn get_scale(&self) -> f64 {
+        self.output.scale()
+    }
+
+    fn get_outputs(&self) -> Vec<Output> {
+        self.outputs.clone()
+    }
+
+    fn open_popup(&mut self, kind: Popup, area: (i16, i16),
+                  size: (u16, u16)) -> Result<cairo::Surface> {
+        let pos = popup_position(&self.output, self.pos, self.size,
+                                 self.edge, kind, area, size);
+
+        self.popup = Some((pos, size));
+        self.popup_kind = kind;
+        self.popup_image = create_image(size.0 as i32, size.1 as i32);
+        Ok(self.popup_image.clone())
+    }
+
+    fn flush_popup(&self) {}
+
+    fn close_popup(&mut self) {
+        self.popup = None;
+    }
+
+    fn popup_events(&mut self) -> Vec<PopupEvent> {
+        mem::replace(&mut self.popup_events, Vec::new())
+    }
+
+    fn enable_tray(&mut self, _: bool) -> Result<()> {
+        if self.tray.is_none() {
+            self.tray = Some(0);
+        }
+        Ok(())
+    }
+
+    fn tray_icons(&self) -> usize {
+        self.tray.unwrap_or(0)
+    }
+
+    fn place_tray(&self, pos: Option<(i16, i16)>, size: u16,
+                  vertical: bool) {
+        self.tray_area.set(pos.map(|p| (p, size, vertical)));
+    }
+
+    fn set_output(&mut self, name: &str) -> Result<()> {
+        match self.outputs.iter().find(|o| o.name == name) {
+            Some(o) => self.output = o.clone(),
+            None    => return Err(Error::NoOutput(String::from(name))),
+        }
+
+        self.reposition_window();
+        Ok(())
+    }
+
+    fn set_name(&mut self, name: &str) -> Result<()> {
+        self.name = String::from(name);
+        Ok(())
+    }
+
+    fn set_class(&mut self, instance: &str, class: &str) -> Result<()> {
+        self.class = (String::from(instance), String::from(class));
+        Ok(())
+    }
+
+    fn set_cursor(&mut self, name: Option<&str>) -> Result<()> {
+        self.cursor = name.map(String::from);
+        Ok(())
+    }
+
+    fn refresh_screen(&mut self) -> Result<bool> {
+        match self.outputs.iter().find(|o| o.name == self.output.name) {
+            Some(o) => self.output = o.clone(),
+            None    => return Ok(false),
+        }
+
+        self.reposition_window();
+        Ok(true)
+    }
+
+    fn flush(&self) {
+        self.flush_area((0, 0), self.size);
+    }
+
+    fn flush_area(&self, pos: (i16, i16), size: (u16, u16)) {
+        unsafe {
+            cairo_sys::cairo_surface_flush(self.image.to_raw_none());
+        }
+        self.flushed.set(Some((pos, size)));
+    }
+
+    fn event_cb<F>(&mut self, f: F)
+        where F: Fn(Event) + Send + Sync + 'static {
+
+        self.event_fn = Box::new(f);
+    }
+
+    fn screen_cb<F>(&mut self, f: F)
+        where F: Fn() + Send + Sync + 'static {
+
+        self.screen_fn = Box::new(f);
+    }
+
+    fn get_fd(&self) -> RawFd {
+        -1
+    }
+
+    fn handle_events(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
@@ -1,17 +1,405 @@
 pub use self::xcbwin::XCB;
+pub use self::headless::Headless;
+pub use self::struts::Struts;
 
+#[cfg(feature = "wayland")]
+pub use self::wlwin::Wayland;
+
+use std::cmp;
+use std::os::unix::io::RawFd;
+use error::Result;
 use cairo;
+use cairo_sys;
 mod xcbwin;
+mod headless;
+mod struts;
+mod tray;
+
+#[cfg(feature = "wayland")]
+mod wlwin;
+
+/// A monitor the bar can be docked on.
+///
+/// The position is relative to the root window, so it can be used
+/// directly when placing windows or computing struts. The physical size
+/// is 0 when it is unknown.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Output {
+    pub name:    String,
+    pub x:       i16,
+    pub y:       i16,
+    pub w:       u16,
+    pub h:       u16,
+    pub mm_w:    u32, // physical width in millimetres
+    pub mm_h:    u32, // physical height in millimetres
+    pub primary: bool,
+}
+
+/// The DPI things are designed for, at a scale of 1.
+pub const BASE_DPI: f64 = 96.0;
+
+impl Output {
+
+    /// Get the scale of the output from its physical size.
+    ///
+    /// The size reported by monitors is only approximate, so the scale is
+    /// rounded to a quarter, and never goes below 1. Outputs of unknown
+    /// size have a scale of 1. The diagonal is used, so that rotated
+    /// outputs get the same scale.
+    pub fn scale(&self) -> f64 {
+        if self.mm_w == 0 || self.mm_h == 0 {
+            return 1.0;
+        }
+
+        let px = (self.w as f64).hypot(self.h as f64);
+        let mm = (self.mm_w as f64).hypot(self.mm_h as f64);
+        let dpi = px / (mm / 25.4);
+
+        ((dpi / BASE_DPI * 4.0).round() / 4.0).max(1.0)
+    }
+}
+
+/// Get the value of `Xft.dpi` from the X resources, as found in the
+/// `RESOURCE_MANAGER` property of the root window.
+pub fn xft_dpi(resources: &str) -> Option<f64> {
+    resources.lines()
+        .filter_map(|l| {
+            let mut kv = l.splitn(2, ':');
+            match (kv.next(), kv.next()) {
+                (Some(k), Some(v)) if k.trim() == "Xft.dpi" => Some(v),
+                _ => None,
+            }
+        })
+        .filter_map(|v| v.trim().parse::<f64>().ok())
+        .filter(|&dpi| dpi > 0.0)
+        .last()
+}
+
+/// The edge of the output a bar is docked on.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Edge {
+    Top,
+    Bottom,
+    Left,
+    Right,
+}
+
+impl Edge {
+
+    /// Whether bars on this edge are vertical.
+    pub fn is_vertical(&self) -> bool {
+        *self == Edge::Left || *self == Edge::Right
+    }
+}
+
+/// The modifier keys held during an input event.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Modifiers {
+    pub shift: bool,
+    pub ctrl:  bool,
+    pub alt:   bool,
+    pub logo:  bool,
+}
+
+impl Modifiers {
+
+    /// Get the modifiers from a core X modifier mask.
+    ///
+    /// xkb uses the same mask for the default modifiers, so this works
+    /// for wayland too.
+    pub fn from_mask(mask: u32) -> Modifiers {
+        Modifiers {
+            shift: mask & 0x01 != 0, // Shift
+            ctrl:  mask & 0x04 != 0, // Control
+            alt:   mask & 0x08 != 0, // Mod1
+            logo:  mask & 0x40 != 0, // Mod4
+        }
+    }
+}
+
+/// An input event on the bar.
+///
+/// Positions are relative to the window, and times are in milliseconds,
+/// as given by the display server.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Event {
+    Press   { x: i16, y: i16, button: u8, mods: Modifiers, time: u32 },
+    Release { x: i16, y: i16, button: u8, mods: Modifiers, time: u32 },
+    Motion  { x: i16, y: i16, mods: Modifiers, time: u32 },
+    Enter   { x: i16, y: i16, mods: Modifiers, time: u32 },
+    Leave   { x: i16, y: i16, mods: Modifiers, time: u32 },
+
+    /// Scrolling, in steps of one per wheel click. Positive values are
+    /// down and to the right.
+    Scroll  { x: i16, y: i16, dx: f64, dy: f64, mods: Modifiers, time: u32 },
+}
+
+impl Event {
+
+    /// Get the position of the pointer.
+    pub fn pos(&self) -> (i16, i16) {
+        match *self {
+            Event::Press   { x, y, .. } |
+            Event::Release { x, y, .. } |
+            Event::Motion  { x, y, .. } |
+            Event::Enter   { x, y, .. } |
+            Event::Leave   { x, y, .. } |
+            Event::Scroll  { x, y, .. } => (x, y),
+        }
+    }
+
+    /// Get the modifiers held during the event.
+    pub fn mods(&self) -> Modifiers {
+        match *self {
+            Event::Press   { mods, .. } |
+            Event::Release { mods, .. } |
+            Event::Motion  { mods, .. } |
+            Event::Enter   { mods, .. } |
+            Event::Leave   { mods, .. } |
+            Event::Scroll  { mods, .. } => mods,
+        }
+    }
+
+    /// Get the time of the event.
+    pub fn time(&self) -> u32 {
+        match *self {
+            Event::Press   { time, .. } |
+            Event::Release { time, .. } |
+            Event::Motion  { time, .. } |
+            Event::Enter   { time, .. } |
+            Event::Leave   { time, .. } |
+            Event::Scroll  { time, .. } => time,
+        }
+    }
+
+    /// Get the button a click or scroll corresponds to.
+    ///
+    /// Scrolling maps to buttons 4 to 7 like in X, which is what
+    /// `%{A4:...}` and friends expect.
+    pub fn button(&self) -> Option<u8> {
+        match *self {
+            Event::Press   { button, .. } |
+            Event::Release { button, .. } => Some(button),
+            Event::Scroll  { dx, dy, .. } => {
+                if dy < 0.0 { Some(4) }
+                else if dy > 0.0 { Some(5) }
+                else if dx < 0.0 { Some(6) }
+                else if dx > 0.0 { Some(7) }
+                else { None }
+            }
+            _ => None,
+        }
+    }
+}
+
+/// The kinds of popups, which are placed and behave differently.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Popup {
+    /// Centred on its region, and ignoring input.
+    Tooltip,
+
+    /// Starting where its region starts. The keyboard and the pointer
+    /// are grabbed while it is open, and their input is reported as
+    /// `PopupEvent`s.
+    Menu,
+}
+
+/// A key pressed in a menu. Other keys are ignored.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Key {
+    Up,
+    Down,
+    Enter,
+    Escape,
+}
+
+/// An input event in a menu, positioned relative to it.
+///
+/// While a menu is open, events anywhere are reported this way, so the
+/// position of presses can be outside of the menu.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PopupEvent {
+    Motion { x: i16, y: i16 },
+    Press  { x: i16, y: i16, button: u8 },
+    Key(Key),
+}
+
+/// Get the position of a window of `size` docked on `edge` of `output`.
+///
+/// The offset is from the corner of the output, towards its inside, so
+/// it is from the bottom for bars at the bottom and from the right for
+/// bars on the right.
+fn place(output: &Output, offset: (i16, i16), size: (u16, u16),
+         edge: Edge) -> (i16, i16) {
+    let x = match edge {
+        Edge::Right => output.x + output.w as i16 - size.0 as i16 - offset.0,
+        _           => output.x + offset.0,
+    };
+
+    let y = match edge {
+        Edge::Bottom => output.y + output.h as i16 - size.1 as i16 - offset.1,
+        _            => output.y + offset.1,
+    };
+
+    (x, y)
+}
+
+/// Get the position of a popup of `kind` and `popup` size next to a bar
+/// at `pos` of `size`, docked on `edge` of `output`.
+///
+/// The popup is put next to `area`, bounds along the bar relative to it.
+/// It goes below bars at the top, above bars at the bottom, and beside
+/// vertical bars, without going over the edges of the output.
+fn popup_position(output: &Output, pos: (i16, i16), size: (u16, u16),
+                  edge: Edge, kind: Popup, area: (i16, i16),
+                  popup: (u16, u16)) -> (i16, i16) {
+    // Line up with the area, but stay on the output
+    let along = |start: i16, len: u16, o_start: i16, o_len: u16| {
+        let v = match kind {
+            Popup::Tooltip => start + (area.0 + area.1) / 2 - len as i16 / 2,
+            Popup::Menu    => start + area.0,
+        };
+        let max = o_start + o_len as i16 - len as i16;
+        cmp::max(cmp::min(v, max), o_start)
+    };
+
+    match edge {
+        Edge::Top    => (along(pos.0, popup.0, output.x, output.w),
+                         pos.1 + size.1 as i16),
+        Edge::Bottom => (along(pos.0, popup.0, output.x, output.w),
+                         pos.1 - popup.1 as i16),
+        Edge::Left   => (pos.0 + size.0 as i16,
+                         along(pos.1, popup.1, output.y, output.h)),
+        Edge::Right  => (pos.0 - popup.0 as i16,
+                         along(pos.1, popup.1, output.y, output.h)),
+    }
+}
+
+/// Get the positions of `n` tray icons of `size` laid out from `pos`,
+/// going down if `vertical`, else right.
+fn tray_positions(pos: (i16, i16), size: u16, vertical: bool, n: usize)
+                  -> Vec<(i16, i16)> {
+    (0..n).map(|i| {
+        let d = (i * size as usize) as i16;
+        if vertical { (pos.0, pos.1 + d) } else { (pos.0 + d, pos.1) }
+    }).collect()
+}
+
+/// Create an ARGB32 cairo image surface.
+///
+/// This is used by the backends which do not draw into a server-side
+/// buffer, and read the pixels back with `cairo_image_surface_get_data`.
+fn create_image(w: i32, h: i32) -> cairo::Surface {
+    unsafe {
+        cairo::Surface::from_raw_full(
+            cairo_sys::cairo_image_surface_create(cairo::Format::ARgb32,
+                                                  w, h))
+    }
+}
 
 pub trait Dock {
-    fn create_surface(&self) -> cairo::Surface;
-    fn dock(&self);
-    fn top(&mut self);
-    fn bottom(&mut self);
-    fn set_size(&mut self, u16, u16);
-    fn set_offset(&mut self, u16, u16);
+    fn create_surface(&self) -> Result<cairo::Surface>;
+    fn dock(&self) -> Result<()>;
+
+    /// Bypass the window manager, for those which do not support docks.
+    ///
+    /// The window is placed and kept above other windows by itself.
+    fn force_dock(&mut self, bool) -> Result<()>;
+
+    fn top(&mut self) -> Result<()>;
+    fn bottom(&mut self) -> Result<()>;
+    fn left(&mut self) -> Result<()>;
+    fn right(&mut self) -> Result<()>;
+    fn set_size(&mut self, u16, u16) -> Result<()>;
+    fn set_offset(&mut self, u16, u16) -> Result<()>;
     fn get_screen_size(&self) -> (u16, u16);
+
+    /// Get the number of pixels per logical pixel on the output the
+    /// window is on.
+    fn get_scale(&self) -> f64;
+    fn get_outputs(&self) -> Vec<Output>;
+    fn set_output(&mut self, &str) -> Result<()>;
+
+    /// Set the name of the window, which window managers show as title.
+    fn set_name(&mut self, &str) -> Result<()>;
+
+    /// Set the class of the window, as an instance and a class name,
+    /// which window managers and compositors use in their rules.
+    fn set_class(&mut self, &str, &str) -> Result<()>;
+
+    /// Show the cursor of the given name over the window, or the default
+    /// one with none.
+    ///
+    /// Names are those of X cursor themes, such as "pointer" or
+    /// "sb_h_double_arrow". Unknown names fail with
+    /// `Error::Unsupported`.
+    fn set_cursor(&mut self, Option<&str>) -> Result<()>;
+
+    /// Take the system tray of the screen, so that tray icons dock in the
+    /// window.
+    ///
+    /// Fails with `Error::Unsupported` if the display server has no such
+    /// thing or if another tray is running.
+    fn enable_tray(&mut self, vertical: bool) -> Result<()>;
+
+    /// Get the number of icons shown in the tray.
+    fn tray_icons(&self) -> usize;
+
+    /// Lay the icons of the tray out from a position in the window, each
+    /// in a square of the given size, going down if vertical. With no
+    /// position, the icons are hidden.
+    fn place_tray(&self, Option<(i16, i16)>, u16, bool);
+
+    /// Open a popup next to the window, and get a surface of the given
+    /// size to draw it on.
+    ///
+    /// The popup is put next to an area along the bar, given by its
+    /// bounds relative to the bar, on the side of the bar facing the
+    /// inside of the output. Opening it again moves and resizes it, and
+    /// can change its kind. `flush_popup` shows what was drawn.
+    fn open_popup(&mut self, Popup, (i16, i16), (u16, u16))
+                  -> Result<cairo::Surface>;
+    fn flush_popup(&self);
+    fn close_popup(&mut self);
+
+    /// Take the input events of the open menu, which `handle_events`
+    /// queues.
+    fn popup_events(&mut self) -> Vec<PopupEvent>;
+
+    /// Update the outputs after a change of the screen layout.
+    ///
+    /// Returns false if the output the window was put on with
+    /// `set_output` is gone.
+    fn refresh_screen(&mut self) -> Result<bool>;
     fn flush(&self);
-    fn click_cb<F>(&mut self, F)
-        where F: Fn(i16, i16, u8) + Send + Sync + 'static;
+
+    /// Show what was drawn in an area of the window, given by its
+    /// position and size.
+    fn flush_area(&self, (i16, i16), (u16, u16));
+
+    /// Set the function called with the input events on the window.
+    ///
+    /// It is called from `handle_events`.
+    fn event_cb<F>(&mut self, F)
+        where F: Fn(Event) + Send + Sync + 'static;
+
+    /// Set the function called when the screen layout changes.
+    ///
+    /// It is called from `handle_events`. The owner of the window is
+    /// expected to call `refresh_screen` once it is done handling events.
+    fn screen_cb<F>(&mut self, F)
+        where F: Fn() + Send + Sync + 'static;
+
+    /// Get the file descriptor of the connection to the display server.
+    ///
+    /// It becomes readable when there are events to handle. A negative
+    /// value means the window has no connection and never gets events.
+    fn get_fd(&self) -> RawFd;
+
+    /// Handle the pending events, without blocking.
+    ///
+    /// The callbacks set with `event_cb` and `screen_cb` are called from
+    /// here, on the caller's thread. Returns `Error::Disconnected` once
+    /// the connection to the display server is lost.
+    fn handle_events(&mut self) -> Result<()>;
 }
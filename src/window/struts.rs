@@ -0,0 +1,78 @@
+use std::cmp;
+
+use window::Edge;
+
+/// The space reserved by a bar at the edge of the screen, as EWMH struts.
+///
+/// Struts are relative to the edges of the root window, not to those of
+/// the output the bar is on. With several outputs, the start and end
+/// values of `_NET_WM_STRUT_PARTIAL` restrict the reserved space to the
+/// extent of the bar along its edge, so that window managers only apply
+/// it to the output the bar is on.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Struts {
+    /// The CARDINALs of `_NET_WM_STRUT_PARTIAL`:
+    ///
+    /// left, right, top, bottom,
+    /// left_start_y, left_end_y, right_start_y, right_end_y,
+    /// top_start_x, top_end_x, bottom_start_x, bottom_end_x
+    pub partial: [u32; 12],
+}
+
+impl Struts {
+
+    /// Compute the struts of a bar at `pos` of the given `size`, on a
+    /// screen of `scr_size`, docked on `edge`.
+    ///
+    /// The reserved space goes from the edge of the screen to the far
+    /// side of the bar, so it includes any gap left between the two.
+    /// Parts of the bar outside of the screen are not reserved.
+    pub fn new(pos: (i16, i16), size: (u16, u16), scr_size: (u16, u16),
+               edge: Edge) -> Struts {
+        let mut data = [0u32; 12];
+
+        // Extent of the bar along the edge, both ends included
+        let (b, e, len) = if edge.is_vertical() {
+            (pos.1 as i32, pos.1 as i32 + size.1 as i32, scr_size.1)
+        } else {
+            (pos.0 as i32, pos.0 as i32 + size.0 as i32, scr_size.0)
+        };
+        let b = cmp::max(b, 0);
+        let e = cmp::min(e, len as i32) - 1;
+
+        if e < b {
+            return Struts { partial: data };
+        }
+
+        // Index of the strut, the space it reserves and its maximum
+        let (i, reserved, max) = match edge {
+            Edge::Left   => (0, pos.0 as i32 + size.0 as i32, scr_size.0),
+            Edge::Right  => (1, scr_size.0 as i32 - pos.0 as i32, scr_size.0),
+            Edge::Top    => (2, pos.1 as i32 + size.1 as i32, scr_size.1),
+            Edge::Bottom => (3, scr_size.1 as i32 - pos.1 as i32, scr_size.1),
+        };
+
+        data[i] = clamp(reserved, max);
+
+        // Nothing reserved, so no range either
+        if data[i] != 0 {
+            data[4 + i * 2] = b as u32;
+            data[5 + i * 2] = e as u32;
+        }
+
+        Struts { partial: data }
+    }
+
+    /// The CARDINALs of the legacy `_NET_WM_STRUT`, for window managers
+    /// which do not know `_NET_WM_STRUT_PARTIAL`.
+    ///
+    /// These reserve the space across the whole width of the screen.
+    pub fn legacy(&self) -> [u32; 4] {
+        [self.partial[0], self.partial[1], self.partial[2], self.partial[3]]
+    }
+}
+
+/// Clamp a strut between 0 and the size of the screen.
+fn clamp(v: i32, max: u16) -> u32 {
+    cmp::min(cmp::max(v, 0), max as i32) as u32
+}
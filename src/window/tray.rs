@@ -0,0 +1,314 @@
+use std::cell::Cell;
+use std::sync::Arc;
+use error::{Error, Result};
+use window::tray_positions;
+
+use xcb::*;
+
+/// The opcode of `_NET_SYSTEM_TRAY_OPCODE` messages asking to dock.
+const SYSTEM_TRAY_REQUEST_DOCK: u32 = 0;
+
+/// The XEmbed message telling a client it was embedded.
+const XEMBED_EMBEDDED_NOTIFY: u32 = 0;
+
+/// The flag of `_XEMBED_INFO` telling whether the client wants to be shown.
+const XEMBED_MAPPED: u32 = 1 << 0;
+
+/// The XEmbed version spoken here.
+const XEMBED_VERSION: u32 = 0;
+
+fn intern(conn: &Connection, name: &str) -> Result<Atom> {
+    Ok(intern_atom(conn, false, name).get_reply()?.atom())
+}
+
+/// An icon docked in the tray.
+struct Icon {
+    win:    Window,
+    mapped: bool, // whether the icon wants to be shown
+}
+
+/// A freedesktop system tray, with the icons embedded in the bar window
+/// through XEmbed.
+///
+/// The bar window owns the `_NET_SYSTEM_TRAY_S<n>` selection, and icons
+/// are reparented into it. They are laid out side by side where the bar
+/// puts the tray with `place`, and moved out of sight until then.
+pub struct Tray {
+    conn:   Arc<Connection>,
+    win:    Window,
+    root:   Window,
+
+    selection: Atom, // _NET_SYSTEM_TRAY_S<n>
+    opcode:    Atom, // _NET_SYSTEM_TRAY_OPCODE
+    xembed:    Atom, // _XEMBED
+    info:      Atom, // _XEMBED_INFO
+
+    icons:  Vec<Icon>,
+    owned:  bool, // false once another tray took the selection
+
+    // The position of the first icon, the size of icons, and whether
+    // they go down rather than right. None until the tray is placed.
+    area:   Cell<Option<((i16, i16), u16, bool)>>,
+}
+
+impl Tray {
+
+    /// Take the system tray of screen `scr_num`, for icons to dock in
+    /// `win`.
+    ///
+    /// Icons are told to use `visual`, so that they can be translucent
+    /// when it has an alpha channel. Fails with `Error::Unsupported` if
+    /// another tray is running.
+    pub fn new(conn: Arc<Connection>, scr_num: i32, root: Window,
+               win: Window, visual: Visualid, vertical: bool)
+               -> Result<Tray> {
+        let selection = intern(&conn,
+                               &format!("_NET_SYSTEM_TRAY_S{}", scr_num))?;
+        let opcode = intern(&conn, "_NET_SYSTEM_TRAY_OPCODE")?;
+        let xembed = intern(&conn, "_XEMBED")?;
+        let info = intern(&conn, "_XEMBED_INFO")?;
+        let manager = intern(&conn, "MANAGER")?;
+
+        let owner = get_selection_owner(&conn, selection).get_reply()?;
+        if owner.owner() != WINDOW_NONE {
+            return Err(Error::Unsupported(
+                String::from("Another system tray is running")));
+        }
+
+        change_property(&conn, PROP_MODE_REPLACE as u8, win,
+                        intern(&conn, "_NET_SYSTEM_TRAY_ORIENTATION")?,
+                        ATOM_CARDINAL, 32, &[vertical as u32]);
+        change_property(&conn, PROP_MODE_REPLACE as u8, win,
+                        intern(&conn, "_NET_SYSTEM_TRAY_VISUAL")?,
+                        ATOM_VISUALID, 32, &[visual]);
+
+        set_selection_owner(&conn, win, selection, TIME_CURRENT_TIME);
+        let owner = get_selection_owner(&conn, selection).get_reply()?;
+        if owner.owner() != win {
+            return Err(Error::Unsupported(
+                String::from("Failed to take the system tray")));
+        }
+
+        // Tell the icons waiting for a tray, as ICCCM managers do
+        let data = ClientMessageData::from_data32([
+            TIME_CURRENT_TIME, selection, win, 0, 0,
+        ]);
+        let ev = ClientMessageEvent::new(32, root, manager, data);
+        send_event(&conn, false, root, EVENT_MASK_STRUCTURE_NOTIFY, &ev)
+            .request_check()?;
+
+        Ok(Tray {
+            conn,
+            win,
+            root,
+            selection,
+            opcode,
+            xembed,
+            info,
+            icons: Vec::new(),
+            owned: true,
+            area:  Cell::new(None),
+        })
+    }
+
+    /// Get the number of icons shown in the tray.
+    pub fn len(&self) -> usize {
+        self.icons.iter().filter(|i| i.mapped).count()
+    }
+
+    /// Lay the icons out from `pos` in the window, each in a square of
+    /// `size`, going down if `vertical`, else right. With no position,
+    /// the icons are moved out of sight.
+    pub fn place(&self, pos: Option<(i16, i16)>, size: u16,
+                 vertical: bool) {
+        self.area.set(pos.map(|p| (p, size, vertical)));
+        self.layout();
+    }
+
+    /// Handle an event if it is about the tray.
+    ///
+    /// Returns false if the event is for someone else.
+    pub fn handle_event(&mut self, e: &GenericEvent) -> bool {
+        match e.response_type() & !0x80 {
+            CLIENT_MESSAGE => {
+                let e: &ClientMessageEvent = unsafe { cast_event(e) };
+                let data = e.data().data32();
+
+                if e.type_() != self.opcode || e.window() != self.win {
+                    return false;
+                }
+
+                if data[1] == SYSTEM_TRAY_REQUEST_DOCK && self.owned {
+                    self.dock(data[2]);
+                }
+            }
+
+            PROPERTY_NOTIFY => {
+                let e: &PropertyNotifyEvent = unsafe { cast_event(e) };
+
+                if e.atom() != self.info || !self.has_icon(e.window()) {
+                    return false;
+                }
+
+                let mapped = self.wants_mapping(e.window());
+                if let Some(i) = self.icons.iter_mut()
+                                     .find(|i| i.win == e.window()) {
+                    i.mapped = mapped;
+                }
+                self.layout();
+            }
+
+            DESTROY_NOTIFY => {
+                let e: &DestroyNotifyEvent = unsafe { cast_event(e) };
+                return self.remove(e.window());
+            }
+
+            REPARENT_NOTIFY => {
+                let e: &ReparentNotifyEvent = unsafe { cast_event(e) };
+
+                // Reparenting into the bar is done when docking
+                if e.parent() == self.win {
+                    return self.has_icon(e.window());
+                }
+                return self.remove(e.window());
+            }
+
+            SELECTION_CLEAR => {
+                let e: &SelectionClearEvent = unsafe { cast_event(e) };
+
+                if e.selection() != self.selection {
+                    return false;
+                }
+
+                // Another tray took over, so it gets the icons
+                self.owned = false;
+                self.release();
+            }
+
+            _ => return false,
+        }
+
+        true
+    }
+
+    fn has_icon(&self, win: Window) -> bool {
+        self.icons.iter().any(|i| i.win == win)
+    }
+
+    /// Embed the window of an icon which asked to dock.
+    fn dock(&mut self, win: Window) {
+        if self.has_icon(win) {
+            return;
+        }
+
+        // The icon may be gone already
+        if get_window_attributes(&self.conn, win).get_reply().is_err() {
+            return;
+        }
+
+        change_window_attributes(&self.conn, win, &[
+                (CW_EVENT_MASK, EVENT_MASK_STRUCTURE_NOTIFY
+                              | EVENT_MASK_PROPERTY_CHANGE),
+        ]);
+
+        // Keep the icon alive if lemonade exits without releasing it
+        change_save_set(&self.conn, SET_MODE_INSERT as u8, win);
+        reparent_window(&self.conn, win, self.win, 0, 0);
+
+        let data = ClientMessageData::from_data32([
+            TIME_CURRENT_TIME, XEMBED_EMBEDDED_NOTIFY, 0, self.win,
+            XEMBED_VERSION,
+        ]);
+        let ev = ClientMessageEvent::new(32, win, self.xembed, data);
+        send_event(&self.conn, false, win, EVENT_MASK_NO_EVENT, &ev);
+
+        let mapped = self.wants_mapping(win);
+        self.icons.push(Icon { win, mapped });
+        self.layout();
+    }
+
+    /// Forget an icon which left. Returns false if it is not docked.
+    fn remove(&mut self, win: Window) -> bool {
+        let len = self.icons.len();
+        self.icons.retain(|i| i.win != win);
+
+        if self.icons.len() == len {
+            return false;
+        }
+
+        self.layout();
+        true
+    }
+
+    /// Give the icons back to the root window.
+    fn release(&mut self) {
+        for i in &self.icons {
+            unmap_window(&self.conn, i.win);
+            reparent_window(&self.conn, i.win, self.root, 0, 0);
+        }
+
+        self.icons.clear();
+        self.conn.flush();
+    }
+
+    /// Whether the icon asks to be shown in its `_XEMBED_INFO`.
+    ///
+    /// Icons without it are shown, as most trays do.
+    fn wants_mapping(&self, win: Window) -> bool {
+        let info = get_property(&self.conn, false, win, self.info,
+                                self.info, 0, 2).get_reply();
+
+        match info {
+            Ok(ref r) if r.value_len() == 2 => {
+                r.value::<u32>()[1] & XEMBED_MAPPED != 0
+            }
+            _ => true,
+        }
+    }
+
+    /// Move the icons to their place, and map those which want to be
+    /// shown.
+    fn layout(&self) {
+        let area = self.area.get();
+        let size = area.map(|(_, s, _)| s).unwrap_or(1);
+
+        let shown: Vec<&Icon> = self.icons.iter().filter(|i| i.mapped)
+                                                 .collect();
+        let positions = match area {
+            Some((pos, size, vertical)) => {
+                tray_positions(pos, size, vertical, shown.len())
+            }
+
+            // Out of sight
+            None => vec![(-(size as i16), -(size as i16)); shown.len()],
+        };
+
+        for (i, &(x, y)) in shown.iter().zip(&positions) {
+            configure_window(&self.conn, i.win, &[
+                    (CONFIG_WINDOW_X as u16, x as u32),
+                    (CONFIG_WINDOW_Y as u16, y as u32),
+                    (CONFIG_WINDOW_WIDTH as u16, size as u32),
+                    (CONFIG_WINDOW_HEIGHT as u16, size as u32),
+            ]);
+            map_window(&self.conn, i.win);
+        }
+
+        for i in self.icons.iter().filter(|i| !i.mapped) {
+            unmap_window(&self.conn, i.win);
+        }
+
+        self.conn.flush();
+    }
+}
+
+impl Drop for Tray {
+    fn drop(&mut self) {
+        self.release();
+
+        if self.owned {
+            set_selection_owner(&self.conn, WINDOW_NONE, self.selection,
+                                TIME_CURRENT_TIME);
+            self.conn.flush();
+        }
+    }
+}
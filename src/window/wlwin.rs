@@ -0,0 +1,784 @@
+use std::cell::{Cell, RefCell};
+use std::cmp;
+use std::fs::File;
+use std::io;
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::ptr;
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+use error::{Error, Result};
+use window::{create_image, Dock, Edge, Event, Modifiers, Output, Popup,
+             PopupEvent};
+
+use cairo;
+use cairo_sys;
+use memmap::MmapMut;
+use tempfile;
+
+use wayland_client::{Attached, Display, EventQueue, GlobalManager, Main};
+use wayland_client::protocol::{wl_buffer, wl_compositor, wl_display,
+                               wl_keyboard, wl_output, wl_pointer,
+                               wl_registry, wl_seat,
+                               wl_shm, wl_surface};
+use wayland_protocols::unstable::xdg_output::v1::client::{
+    zxdg_output_manager_v1, zxdg_output_v1};
+use wayland_protocols::wlr::unstable::layer_shell::v1::client::{
+    zwlr_layer_shell_v1, zwlr_layer_surface_v1};
+
+use self::zwlr_layer_surface_v1::Anchor;
+
+// Linux input event codes for the mouse buttons
+const BTN_LEFT:   u32 = 0x110;
+const BTN_RIGHT:  u32 = 0x111;
+const BTN_MIDDLE: u32 = 0x112;
+
+/// Map a linux button code to the X button numbering used in `Event`.
+fn map_button(b: u32) -> Option<u8> {
+    match b {
+        BTN_LEFT   => Some(1),
+        BTN_MIDDLE => Some(2),
+        BTN_RIGHT  => Some(3),
+        _          => None,
+    }
+}
+
+/// An shm buffer, busy from when it is attached until the compositor
+/// releases it.
+struct Buffer {
+    buffer: Main<wl_buffer::WlBuffer>,
+    mmap:   MmapMut,
+    busy:   Rc<Cell<bool>>,
+}
+
+pub struct Wayland {
+
+    display:     Display,
+    attached:    Attached<wl_display::WlDisplay>,
+    queue:       RefCell<EventQueue>,
+
+    compositor:  Main<wl_compositor::WlCompositor>,
+    shm:         Main<wl_shm::WlShm>,
+    layer_shell: Main<zwlr_layer_shell_v1::ZwlrLayerShellV1>,
+    xdg_outputs: Main<zxdg_output_manager_v1::ZxdgOutputManagerV1>,
+
+    surface:     Main<wl_surface::WlSurface>,
+    surface_id:  Rc<Cell<u32>>, // of the current surface, for the pointer
+    layer:       Main<zwlr_layer_surface_v1::ZwlrLayerSurfaceV1>,
+    closed:      Rc<Cell<bool>>,
+    buffers:     RefCell<Vec<Buffer>>,
+    pending:     Cell<bool>, // whether a frame waits for a free buffer
+    image:       cairo::Surface,
+
+    outputs:     Vec<(Main<wl_output::WlOutput>, Output)>,
+    output:      Option<String>, // None lets the compositor choose
+    name:        String, // namespace of the layer surface
+
+    size:        (u16, u16), // (w, h)
+    offset:      (u16, u16), // (x, y)
+    edge:        Edge,
+
+    event_fn:    Arc<Mutex<Box<Fn(Event) + Sync + Send>>>,
+    screen_fn:   Arc<Mutex<Box<Fn() + Sync + Send>>>,
+}
+
+impl Wayland {
+    /// Connect to the compositor in `$WAYLAND_DISPLAY` and create the
+    /// surface.
+    pub fn new() -> Result<Wayland> {
+        let display = Display::connect_to_env()
+            .map_err(|e| Error::Connection(e.to_string()))?;
+        let mut queue = display.create_event_queue();
+        let attached = (*display).clone().attach(queue.token());
+
+        let globals = GlobalManager::new(&attached);
+        queue.sync_roundtrip(&mut (), |_, _, _| {})?;
+
+        let unsupported = |name: &str| {
+            Error::Unsupported(format!("Compositor does not support {}", name))
+        };
+
+        let compositor = globals
+            .instantiate_exact::<wl_compositor::WlCompositor>(4)
+            .map_err(|_| unsupported("wl_compositor"))?;
+        let shm = globals
+            .instantiate_exact::<wl_shm::WlShm>(1)
+            .map_err(|_| unsupported("wl_shm"))?;
+        let layer_shell = globals
+            .instantiate_exact::<zwlr_layer_shell_v1::ZwlrLayerShellV1>(1)
+            .map_err(|_| unsupported("wlr-layer-shell"))?;
+        let xdg_outputs = globals
+            .instantiate_exact::<zxdg_output_manager_v1::ZxdgOutputManagerV1>(2)
+            .map_err(|_| unsupported("xdg-output"))?;
+
+        let surface = compositor.create_surface();
+        let layer = layer_shell.get_layer_surface(
+            &surface, None, zwlr_layer_shell_v1::Layer::Top,
+            String::from("lemonade"));
+
+        let event_fn: Arc<Mutex<Box<Fn(Event) + Sync + Send>>> =
+            Arc::new(Mutex::new(Box::new(|_| {} // Placeholder closure
+        )));
+        let screen_fn: Arc<Mutex<Box<Fn() + Sync + Send>>> =
+            Arc::new(Mutex::new(Box::new(|| {})));
+
+        let mut x = Wayland {
+            display,
+            attached,
+            queue:   RefCell::new(queue),
+            compositor,
+            shm,
+            layer_shell,
+            xdg_outputs,
+            surface_id: Rc::new(Cell::new(surface.as_ref().id())),
+            surface,
+            layer,
+            closed:  Rc::new(Cell::new(false)),
+            buffers: RefCell::new(Vec::new()),
+            pending: Cell::new(false),
+            image:   create_image(1, 1),
+            outputs: Vec::new(),
+            output:  None,
+            name:    String::from("lemonade"),
+            size:    (1, 1),
+            offset:  (0, 0),
+            edge:    Edge::Top,
+            event_fn,
+            screen_fn,
+        };
+
+        x.query_outputs()?;
+        x.init_layer()?;
+        x.listen_input()?;
+
+        Ok(x)
+    }
+
+    fn roundtrip(&self) -> Result<()> {
+        self.queue.borrow_mut().sync_roundtrip(&mut (), |_, _, _| {})?;
+        Ok(())
+    }
+
+    /// Get the current outputs along with their name and logical geometry.
+    ///
+    /// A new registry is used every time, so that outputs which appeared
+    /// since the last call are picked up too.
+    fn query_outputs(&mut self) -> Result<()> {
+        let found = Rc::new(RefCell::new(Vec::new()));
+
+        let registry = self.attached.get_registry();
+        {
+            let found = found.clone();
+            registry.quick_assign(move |reg, event, _| {
+                if let wl_registry::Event::Global {
+                    name, interface, version
+                } = event {
+                    if interface == "wl_output" {
+                        let o = reg.bind::<wl_output::WlOutput>(
+                            cmp::min(version, 3), name);
+                        found.borrow_mut().push(o);
+                    }
+                }
+            });
+        }
+        self.roundtrip()?;
+
+        let info: Rc<RefCell<Vec<Output>>> = Rc::new(RefCell::new(Vec::new()));
+        for (i, o) in found.borrow().iter().enumerate() {
+            info.borrow_mut().push(Output {
+                name:    String::new(),
+                x:       0,
+                y:       0,
+                w:       0,
+                h:       0,
+                mm_w:    0,
+                mm_h:    0,
+                // Wayland has no notion of a primary output, so the first
+                // one is used, like with XCB when no primary is set.
+                primary: i == 0,
+            });
+
+            let info = info.clone();
+            let xo = self.xdg_outputs.get_xdg_output(o);
+            xo.quick_assign(move |_, event, _| {
+                let mut info = info.borrow_mut();
+                let out = &mut info[i];
+
+                match event {
+                    zxdg_output_v1::Event::LogicalPosition { x, y } => {
+                        out.x = x as i16;
+                        out.y = y as i16;
+                    }
+
+                    zxdg_output_v1::Event::LogicalSize { width, height } => {
+                        out.w = width as u16;
+                        out.h = height as u16;
+                    }
+
+                    zxdg_output_v1::Event::Name { name } => {
+                        out.name = name;
+                    }
+
+                    _ => {}
+                }
+            });
+        }
+        self.roundtrip()?;
+
+        let info = info.borrow().clone();
+        self.outputs = found.borrow().iter().cloned().zip(info).collect();
+        Ok(())
+    }
+
+    /// Get the output the bar is on, or would be put on by default.
+    fn current_output(&self) -> Option<&Output> {
+        match self.output {
+            Some(ref n) => self.outputs.iter()
+                                       .map(|&(_, ref o)| o)
+                                       .find(|o| &o.name == n),
+            None        => self.outputs.first().map(|&(_, ref o)| o),
+        }
+    }
+
+    /// Set up the event handling of a new layer surface.
+    fn init_layer(&mut self) -> Result<()> {
+        let closed = self.closed.clone();
+        closed.set(false);
+
+        self.layer.quick_assign(move |layer, event, _| {
+            match event {
+                zwlr_layer_surface_v1::Event::Configure { serial, .. } => {
+                    layer.ack_configure(serial);
+                }
+
+                zwlr_layer_surface_v1::Event::Closed => {
+                    closed.set(true);
+                }
+
+                _ => {}
+            }
+        });
+
+        self.reposition_window()
+    }
+
+    /// Recreate the surface on the output chosen with `set_output`.
+    ///
+    /// The output of a layer surface cannot be changed once it is created,
+    /// so this is the only way to move the bar.
+    fn recreate_layer(&mut self) -> Result<()> {
+        self.layer.destroy();
+        self.surface.destroy();
+
+        let output = match self.output {
+            Some(ref n) => self.outputs.iter()
+                                       .find(|&&(_, ref o)| &o.name == n)
+                                       .map(|&(ref wo, _)| wo.detach()),
+            None        => None,
+        };
+
+        self.surface = self.compositor.create_surface();
+        self.surface_id.set(self.surface.as_ref().id());
+        self.layer = self.layer_shell.get_layer_surface(
+            &self.surface, output.as_ref(),
+            zwlr_layer_shell_v1::Layer::Top, self.name.clone());
+
+        self.init_layer()?;
+
+        let (w, h) = self.size;
+        self.set_size(w, h)
+    }
+
+    /// Anchor the surface to its edge and update the exclusive zone.
+    ///
+    /// The exclusive zone is the wayland equivalent of the struts.
+    fn reposition_window(&mut self) -> Result<()> {
+        // Vertical bars start at the top, horizontal ones at the left
+        let (x, y) = (self.offset.0 as i32, self.offset.1 as i32);
+        match self.edge {
+            Edge::Top => {
+                self.layer.set_anchor(Anchor::Top | Anchor::Left);
+                self.layer.set_margin(y, 0, 0, x);
+            }
+            Edge::Bottom => {
+                self.layer.set_anchor(Anchor::Bottom | Anchor::Left);
+                self.layer.set_margin(0, 0, y, x);
+            }
+            Edge::Left => {
+                self.layer.set_anchor(Anchor::Left | Anchor::Top);
+                self.layer.set_margin(y, 0, 0, x);
+            }
+            Edge::Right => {
+                self.layer.set_anchor(Anchor::Right | Anchor::Top);
+                self.layer.set_margin(y, x, 0, 0);
+            }
+        }
+
+        self.layer.set_exclusive_zone(self.thickness());
+
+        self.layer.set_size(self.size.0 as u32, self.size.1 as u32);
+        self.surface.commit();
+        self.roundtrip()
+    }
+
+    /// The size of the surface across its edge, which is the space the
+    /// compositor keeps free for it.
+    fn thickness(&self) -> i32 {
+        if self.edge.is_vertical() {
+            self.size.0 as i32
+        } else {
+            self.size.1 as i32
+        }
+    }
+
+    /// Create new shm buffers matching the size of the surface.
+    ///
+    /// There are two of them, so that one can be drawn into while the
+    /// compositor still reads the other.
+    fn create_buffers(&mut self) -> Result<()> {
+        let (w, h) = (self.size.0 as i32, self.size.1 as i32);
+        let stride = w * 4;
+
+        let mut buffers = Vec::with_capacity(2);
+        for _ in 0..2 {
+            let file = tempfile::tempfile()?;
+            file.set_len((stride * h) as u64)?;
+            let mmap = unsafe { MmapMut::map_mut(&file) }?;
+
+            let pool = self.shm.create_pool(file.as_raw_fd(), stride * h);
+            let buffer = pool.create_buffer(0, w, h, stride,
+                                            wl_shm::Format::Argb8888);
+            pool.destroy();
+
+            let busy = Rc::new(Cell::new(false));
+            let b = busy.clone();
+            buffer.quick_assign(move |_, event, _| {
+                if let wl_buffer::Event::Release = event {
+                    b.set(false);
+                }
+            });
+
+            buffers.push(Buffer { buffer, mmap, busy });
+        }
+
+        let mut old = self.buffers.borrow_mut();
+        for b in old.iter() {
+            b.buffer.destroy();
+        }
+
+        *old = buffers;
+        Ok(())
+    }
+
+    /// Listen for pointer events and for outputs being added or removed.
+    ///
+    /// The events are dispatched by `handle_events`.
+    fn listen_input(&self) -> Result<()> {
+        let event_fn = self.event_fn.clone();
+        let screen_fn = self.screen_fn.clone();
+        let surface_id = self.surface_id.clone();
+
+        // Whether the initial globals have been received
+        let ready = Rc::new(Cell::new(false));
+
+        let registry = self.attached.get_registry();
+        let r = ready.clone();
+        registry.quick_assign(move |reg, event, _| {
+            match event {
+                wl_registry::Event::Global { name, interface, .. } => {
+                    if interface == "wl_seat" {
+                        let seat = reg.bind::<wl_seat::WlSeat>(1, name);
+                        handle_seat(&seat, surface_id.clone(),
+                                    event_fn.clone());
+                    } else if interface == "wl_output" && r.get() {
+                        let f = screen_fn.lock().unwrap();
+                        f();
+                    }
+                }
+
+                wl_registry::Event::GlobalRemove { .. } => {
+                    let f = screen_fn.lock().unwrap();
+                    f();
+                }
+
+                _ => {}
+            }
+        });
+
+        self.roundtrip()?;
+        ready.set(true);
+        Ok(())
+    }
+}
+
+/// Forward the pointer events over the bar to the event callback.
+///
+/// The keyboard is only used to keep track of the modifiers.
+fn handle_seat(seat: &Main<wl_seat::WlSeat>, surface_id: Rc<Cell<u32>>,
+               event_fn: Arc<Mutex<Box<Fn(Event) + Sync + Send>>>) {
+    let mods = Rc::new(Cell::new(Modifiers::default()));
+
+    seat.quick_assign(move |seat, event, _| {
+        let caps = match event {
+            wl_seat::Event::Capabilities { capabilities } => capabilities,
+            _ => return,
+        };
+
+        if caps.contains(wl_seat::Capability::Keyboard) {
+            let mods = mods.clone();
+            seat.get_keyboard().quick_assign(move |_, event, _| {
+                match event {
+                    // The keymap is not needed, but the fd must be closed
+                    wl_keyboard::Event::Keymap { fd, .. } => unsafe {
+                        File::from_raw_fd(fd);
+                    },
+
+                    wl_keyboard::Event::Modifiers {
+                        mods_depressed, mods_latched, ..
+                    } => {
+                        let m = mods_depressed | mods_latched;
+                        mods.set(Modifiers::from_mask(m));
+                    }
+
+                    _ => {}
+                }
+            });
+        }
+
+        if caps.contains(wl_seat::Capability::Pointer) {
+            handle_pointer(&seat.get_pointer(), surface_id.clone(),
+                           mods.clone(), event_fn.clone());
+        }
+    });
+}
+
+/// Surfaces are compared with `surface_id` when the pointer enters, which
+/// follows the surface as it is recreated.
+fn handle_pointer(pointer: &Main<wl_pointer::WlPointer>,
+                  surface_id: Rc<Cell<u32>>, mods: Rc<Cell<Modifiers>>,
+                  event_fn: Arc<Mutex<Box<Fn(Event) + Sync + Send>>>) {
+    let mut inside = false;
+    let mut pos = (0i16, 0i16);
+    let mut last_time = 0;
+
+    pointer.quick_assign(move |_, event, _| {
+        let mods = mods.get();
+        let (x, y) = pos;
+
+        let e = match event {
+            wl_pointer::Event::Enter {
+                surface, surface_x, surface_y, ..
+            } => {
+                inside = surface.as_ref().id() == surface_id.get();
+                pos = (surface_x as i16, surface_y as i16);
+
+                // Enter events have no time, use the last known one
+                Event::Enter { x: pos.0, y: pos.1, mods, time: last_time }
+            }
+
+            wl_pointer::Event::Leave { .. } => {
+                if !inside {
+                    return;
+                }
+
+                inside = false;
+                Event::Leave { x, y, mods, time: last_time }
+            }
+
+            wl_pointer::Event::Motion { time, surface_x, surface_y } => {
+                pos = (surface_x as i16, surface_y as i16);
+                last_time = time;
+
+                Event::Motion { x: pos.0, y: pos.1, mods, time }
+            }
+
+            wl_pointer::Event::Button { time, button, state, .. } => {
+                last_time = time;
+
+                let button = match map_button(button) {
+                    Some(b) => b,
+                    None    => return,
+                };
+
+                if state == wl_pointer::ButtonState::Pressed {
+                    Event::Press { x, y, button, mods, time }
+                } else {
+                    Event::Release { x, y, button, mods, time }
+                }
+            }
+
+            wl_pointer::Event::Axis { time, axis, value } => {
+                last_time = time;
+
+                // One wheel click is usually 10 units
+                let v = value / 10.0;
+                let (dx, dy) = match axis {
+                    wl_pointer::Axis::VerticalScroll   => (0.0, v),
+                    wl_pointer::Axis::HorizontalScroll => (v, 0.0),
+                    _ => return,
+                };
+
+                Event::Scroll { x, y, dx, dy, mods, time }
+            }
+
+            _ => return,
+        };
+
+        if inside {
+            let f = event_fn.lock().unwrap();
+            f(e);
+        }
+    });
+}
+
+
+impl Dock for Wayland {
+    fn create_surface(&self) -> Result<cairo::Surface> {
+        Ok(self.image.clone())
+    }
+
+    fn dock(&self) -> Result<()> {
+        self.layer.set_exclusive_zone(self.thickness());
+        self.surface.commit();
+        Ok(())
+    }
+
+    /// Layer surfaces are placed by the compositor itself, so there is
+    /// nothing to force.
+    fn force_dock(&mut self, _: bool) -> Result<()> {
+        Ok(())
+    }
+
+    fn top(&mut self) -> Result<()> {
+        self.edge = Edge::Top;
+        self.reposition_window()
+    }
+
+    fn bottom(&mut self) -> Result<()> {
+        self.edge = Edge::Bottom;
+        self.reposition_window()
+    }
+
+    fn left(&mut self) -> Result<()> {
+        self.edge = Edge::Left;
+        self.reposition_window()
+    }
+
+    fn right(&mut self) -> Result<()> {
+        self.edge = Edge::Right;
+        self.reposition_window()
+    }
+
+    fn set_size(&mut self, w: u16, h: u16) -> Result<()> {
+        self.size = (w, h);
+        self.image = create_image(w as i32, h as i32);
+        self.create_buffers()?;
+        self.reposition_window()
+    }
+
+    fn set_offset(&mut self, x: u16, y: u16) -> Result<()> {
+        self.offset = (x, y);
+        self.reposition_window()
+    }
+
+    /// Get the logical size of the output the bar is on.
+    fn get_screen_size(&self) -> (u16, u16) {
+        match self.current_output() {
+            Some(o) => (o.w, o.h),
+            None    => self.size,
+        }
+    }
+
+    /// Sizes are logical, and the compositor scales the bar along with
+    /// its output, so there is nothing to scale here.
+    fn get_scale(&self) -> f64 {
+        1.0
+    }
+
+    /// There is no XEmbed on wayland, and tray icons are left to the
+    /// StatusNotifierItem protocol, which needs a D-Bus host.
+    fn enable_tray(&mut self, _: bool) -> Result<()> {
+        Err(Error::Unsupported(String::from("No system tray on wayland")))
+    }
+
+    fn tray_icons(&self) -> usize {
+        0
+    }
+
+    /// Popups need xdg_popup, which is not supported yet.
+    fn open_popup(&mut self, _: Popup, _: (i16, i16), _: (u16, u16))
+                  -> Result<cairo::Surface> {
+        Err(Error::Unsupported(String::from("No popups on wayland")))
+    }
+
+    fn flush_popup(&self) {}
+
+    fn close_popup(&mut self) {}
+
+    fn popup_events(&mut self) -> Vec<PopupEvent> {
+        Vec::new()
+    }
+
+    fn place_tray(&self, _: Option<(i16, i16)>, _: u16, _: bool) {}
+
+    fn get_outputs(&self) -> Vec<Output> {
+        self.outputs.iter().map(|&(_, ref o)| o.clone()).collect()
+    }
+
+    fn set_output(&mut self, name: &str) -> Result<()> {
+        self.query_outputs()?;
+        if !self.outputs.iter().any(|&(_, ref o)| o.name == name) {
+            return Err(Error::NoOutput(String::from(name)));
+        }
+
+        self.output = Some(String::from(name));
+        self.recreate_layer()
+    }
+
+    /// Set the namespace of the layer surface.
+    ///
+    /// Layer surfaces have no title, but compositors use the namespace
+    /// to tell them apart. It cannot be changed on an existing surface,
+    /// so the surface is created again.
+    fn set_name(&mut self, name: &str) -> Result<()> {
+        self.name = String::from(name);
+        self.recreate_layer()
+    }
+
+    /// Layer surfaces have no class, the namespace set with `set_name`
+    /// is all compositors have to go by.
+    fn set_class(&mut self, _: &str, _: &str) -> Result<()> {
+        Ok(())
+    }
+
+    /// Cursors are set through wl_pointer, with images from a cursor
+    /// theme, which is not supported yet.
+    fn set_cursor(&mut self, name: Option<&str>) -> Result<()> {
+        match name {
+            Some(_) => Err(Error::Unsupported(
+                String::from("No cursor shapes on wayland"))),
+            None    => Ok(()),
+        }
+    }
+
+    /// Update the outputs after a change.
+    ///
+    /// The compositor closes the layer surface when its output goes away,
+    /// in which case false is returned if the output was chosen with
+    /// `set_output`. Otherwise the surface is recreated on the default
+    /// output.
+    fn refresh_screen(&mut self) -> Result<bool> {
+        self.query_outputs()?;
+
+        let gone = self.closed.get() || self.current_output().is_none();
+        if gone {
+            if self.output.is_some() {
+                return Ok(false);
+            }
+
+            self.recreate_layer()?;
+        }
+
+        Ok(true)
+    }
+
+    /// The whole buffer is attached again anyway.
+    fn flush_area(&self, _: (i16, i16), _: (u16, u16)) {
+        self.flush();
+    }
+
+    fn flush(&self) {
+        // Handle configure events sent since the last flush
+        let _ = self.queue.borrow_mut().dispatch_pending(&mut (), |_, _, _| {});
+
+        let mut buffers = self.buffers.borrow_mut();
+        let buf = match buffers.iter_mut().find(|b| !b.busy.get()) {
+            Some(b) => b,
+            None    => {
+                // Both are still read, so this waits for a release
+                self.pending.set(true);
+                return;
+            }
+        };
+        self.pending.set(false);
+
+        // Copy the image into the shm buffer. Both are ARGB32 with
+        // a stride of 4 * width, so this is a plain copy.
+        unsafe {
+            let img = self.image.to_raw_none();
+            cairo_sys::cairo_surface_flush(img);
+
+            let data = cairo_sys::cairo_image_surface_get_data(img);
+            let stride = cairo_sys::cairo_image_surface_get_stride(img);
+            let len = cmp::min(buf.mmap.len(),
+                               (stride * self.size.1 as i32) as usize);
+
+            ptr::copy_nonoverlapping(data, buf.mmap.as_mut_ptr(), len);
+        }
+
+        self.surface.attach(Some(&buf.buffer), 0, 0);
+        buf.busy.set(true);
+        self.surface.damage_buffer(0, 0,
+                                   self.size.0 as i32, self.size.1 as i32);
+        self.surface.commit();
+
+        let _ = self.display.flush();
+    }
+
+    fn event_cb<F>(&mut self, f: F)
+        where F: Fn(Event) + Send + Sync + 'static {
+
+        let mut cb = self.event_fn.lock().unwrap();
+        *cb = Box::new(f);
+    }
+
+    fn screen_cb<F>(&mut self, f: F)
+        where F: Fn() + Send + Sync + 'static {
+
+        let mut cb = self.screen_fn.lock().unwrap();
+        *cb = Box::new(f);
+    }
+
+    fn get_fd(&self) -> RawFd {
+        self.display.get_connection_fd()
+    }
+
+    fn handle_events(&mut self) -> Result<()> {
+        let disconnected = |e: io::Error| Error::Disconnected(e.to_string());
+        {
+            let mut queue = self.queue.borrow_mut();
+
+            // No guard means there are events queued already
+            if let Some(guard) = queue.prepare_read() {
+                match guard.read_events() {
+                    Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {}
+                    r => r.map_err(&disconnected)?,
+                }
+            }
+
+            queue.dispatch_pending(&mut (), |_, _, _| {})
+                 .map_err(&disconnected)?;
+        }
+
+        // Show the frame which waited for a buffer to be released
+        if self.pending.get() {
+            self.flush();
+        }
+
+        match self.display.flush() {
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => Ok(()),
+            r => r.map_err(&disconnected),
+        }
+    }
+}
+
+impl Drop for Wayland {
+    fn drop(&mut self) {
+        for b in self.buffers.borrow().iter() {
+            b.buffer.destroy();
+        }
+
+        self.layer.destroy();
+        self.surface.destroy();
+        let _ = self.display.flush();
+    }
+}
@@ -1,16 +1,33 @@
+use std::collections::HashMap;
+use std::mem;
 use std::sync::Arc;
 use std::sync::Mutex;
+use std::sync::Once;
 use std::ops::Drop;
-use std::thread;
-use window::Dock;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::process;
+use error::{Error, Result};
+use window::{place, popup_position, xft_dpi, Dock, Edge, Event, Key,
+             Modifiers, Output, Popup, PopupEvent, Struts, BASE_DPI};
+use window::tray::Tray;
 
 use cairo;
 use cairo::XCBSurface;
 use cairo_sys;
 use xcb;
 use xcb::*;
+use xcb::randr;
 
+use libc;
 
+
+/// Warned about the lack of a 32-bit visual, once for all bars.
+static NO_ALPHA: Once = Once::new();
+
+/// Find a visual of the given depth.
+///
+/// Without a 32-bit visual the window has no alpha, so translucent
+/// colours end up opaque whether there is a compositor or not.
 fn get_visualid_from_depth(scr: Screen, depth: u8) -> (Visualid, u8) {
     for d in scr.allowed_depths() {
         if depth == d.depth() {
@@ -24,6 +41,269 @@ fn get_visualid_from_depth(scr: Screen, depth: u8) -> (Visualid, u8) {
     return (scr.root_visual(), scr.root_depth());
 }
 
+/// Convert a core X pointer event into an `Event`.
+///
+/// Buttons 4 to 7 are the scroll wheel in X, so their presses are turned
+/// into `Event::Scroll` and their releases are dropped.
+fn pointer_event(e: &GenericEvent) -> Option<Event> {
+    match e.response_type() & !0x80 {
+        BUTTON_PRESS => {
+            let e: &ButtonPressEvent = unsafe { cast_event(e) };
+            let (x, y) = (e.event_x(), e.event_y());
+            let mods = Modifiers::from_mask(e.state() as u32);
+            let time = e.time();
+
+            let (dx, dy) = match e.detail() {
+                4 => (0.0, -1.0),
+                5 => (0.0, 1.0),
+                6 => (-1.0, 0.0),
+                7 => (1.0, 0.0),
+                b => return Some(Event::Press {
+                    x, y, button: b, mods, time
+                }),
+            };
+
+            Some(Event::Scroll { x, y, dx, dy, mods, time })
+        }
+
+        BUTTON_RELEASE => {
+            let e: &ButtonReleaseEvent = unsafe { cast_event(e) };
+
+            match e.detail() {
+                4...7 => None,
+                b     => Some(Event::Release {
+                    x:      e.event_x(),
+                    y:      e.event_y(),
+                    button: b,
+                    mods:   Modifiers::from_mask(e.state() as u32),
+                    time:   e.time(),
+                }),
+            }
+        }
+
+        MOTION_NOTIFY => {
+            let e: &MotionNotifyEvent = unsafe { cast_event(e) };
+
+            Some(Event::Motion {
+                x:    e.event_x(),
+                y:    e.event_y(),
+                mods: Modifiers::from_mask(e.state() as u32),
+                time: e.time(),
+            })
+        }
+
+        ENTER_NOTIFY => {
+            let e: &EnterNotifyEvent = unsafe { cast_event(e) };
+
+            Some(Event::Enter {
+                x:    e.event_x(),
+                y:    e.event_y(),
+                mods: Modifiers::from_mask(e.state() as u32),
+                time: e.time(),
+            })
+        }
+
+        LEAVE_NOTIFY => {
+            let e: &LeaveNotifyEvent = unsafe { cast_event(e) };
+
+            Some(Event::Leave {
+                x:    e.event_x(),
+                y:    e.event_y(),
+                mods: Modifiers::from_mask(e.state() as u32),
+                time: e.time(),
+            })
+        }
+
+        _ => None,
+    }
+}
+
+/// The glyphs of the X cursor font, by the names of cursor themes.
+///
+/// Both the X names and the freedesktop ones, which are those of CSS, are
+/// accepted.
+const CURSORS: &[(&str, u16)] = &[
+    ("left_ptr",          68),
+    ("default",           68),
+    ("hand2",             60),
+    ("pointer",           60),
+    ("hand1",             58),
+    ("xterm",             152),
+    ("text",              152),
+    ("sb_h_double_arrow", 108),
+    ("ew-resize",         108),
+    ("col-resize",        108),
+    ("sb_v_double_arrow", 116),
+    ("ns-resize",         116),
+    ("row-resize",        116),
+    ("fleur",             52),
+    ("move",              52),
+    ("crosshair",         34),
+    ("watch",             150),
+    ("wait",              150),
+    ("question_arrow",    92),
+    ("help",              92),
+];
+
+/// Keysyms of the keys used in menus.
+const XK_RETURN:   Keysym = 0xff0d;
+const XK_ESCAPE:   Keysym = 0xff1b;
+const XK_UP:       Keysym = 0xff52;
+const XK_DOWN:     Keysym = 0xff54;
+const XK_KP_ENTER: Keysym = 0xff8d;
+
+/// Convert an event reported to an open menu into a `PopupEvent`.
+///
+/// Keys are looked up by the first keysym of their keycode, so that they
+/// work whatever the modifiers.
+fn menu_event(conn: &Connection, e: &GenericEvent) -> Option<PopupEvent> {
+    match e.response_type() & !0x80 {
+        BUTTON_PRESS => {
+            let e: &ButtonPressEvent = unsafe { cast_event(e) };
+
+            Some(PopupEvent::Press {
+                x:      e.event_x(),
+                y:      e.event_y(),
+                button: e.detail(),
+            })
+        }
+
+        MOTION_NOTIFY => {
+            let e: &MotionNotifyEvent = unsafe { cast_event(e) };
+            Some(PopupEvent::Motion { x: e.event_x(), y: e.event_y() })
+        }
+
+        KEY_PRESS => {
+            let e: &KeyPressEvent = unsafe { cast_event(e) };
+            let map = get_keyboard_mapping(conn, e.detail(), 1)
+                .get_reply().ok()?;
+
+            let key = match map.keysyms().first() {
+                Some(&XK_UP)                    => Key::Up,
+                Some(&XK_DOWN)                  => Key::Down,
+                Some(&XK_RETURN)
+                | Some(&XK_KP_ENTER)            => Key::Enter,
+                Some(&XK_ESCAPE)                => Key::Escape,
+                _                               => return None,
+            };
+
+            Some(PopupEvent::Key(key))
+        }
+
+        _ => None,
+    }
+}
+
+/// Query the connected and enabled RandR outputs of the screen.
+///
+/// Returns an empty vector if the RandR extension is not available,
+/// in which case the whole screen should be used instead.
+fn query_outputs(conn: &Connection, root: Window) -> Vec<Output> {
+    let mut r = Vec::new();
+
+    match conn.get_extension_data(randr::id()) {
+        Some(ref ext) if ext.present() => {}
+        _ => return r,
+    }
+
+    let res = match randr::get_screen_resources_current(conn, root)
+                          .get_reply() {
+        Ok(res) => res,
+        Err(_)  => return r,
+    };
+
+    let primary = match randr::get_output_primary(conn, root).get_reply() {
+        Ok(p)  => p.output(),
+        Err(_) => 0,
+    };
+
+    let ts = res.config_timestamp();
+    for &o in res.outputs() {
+        let info = match randr::get_output_info(conn, o, ts).get_reply() {
+            Ok(info) => info,
+            Err(_)   => continue,
+        };
+
+        // Skip disconnected outputs and those without a crtc (disabled)
+        if info.connection() != randr::CONNECTION_CONNECTED as u8
+            || info.crtc() == 0 {
+            continue;
+        }
+
+        let crtc = match randr::get_crtc_info(conn, info.crtc(), ts)
+                               .get_reply() {
+            Ok(crtc) => crtc,
+            Err(_)   => continue,
+        };
+
+        r.push(Output {
+            name:    String::from_utf8_lossy(info.name()).into_owned(),
+            x:       crtc.x(),
+            y:       crtc.y(),
+            w:       crtc.width(),
+            h:       crtc.height(),
+            mm_w:    info.mm_width(),
+            mm_h:    info.mm_height(),
+            primary: o == primary,
+        });
+    }
+
+    return r;
+}
+
+/// The events the window listens to.
+const WINDOW_EVENTS: u32 = EVENT_MASK_BUTTON_PRESS
+                         | EVENT_MASK_BUTTON_RELEASE
+                         | EVENT_MASK_POINTER_MOTION
+                         | EVENT_MASK_ENTER_WINDOW
+                         | EVENT_MASK_LEAVE_WINDOW
+                         | EVENT_MASK_EXPOSURE;
+
+/// The properties wallpaper setters put the root pixmap in, by order of
+/// preference.
+const ROOT_PIXMAP_ATOMS: [&str; 2] = ["_XROOTPMAP_ID", "ESETROOT_PMAP_ID"];
+
+/// Get the name of the host, for `WM_CLIENT_MACHINE`.
+fn hostname() -> String {
+    let mut buf = [0u8; 256];
+
+    let r = unsafe {
+        libc::gethostname(buf.as_mut_ptr() as *mut libc::c_char, buf.len())
+    };
+    if r != 0 {
+        return String::new();
+    }
+
+    let len = buf.iter().position(|&c| c == 0).unwrap_or(buf.len());
+    String::from_utf8_lossy(&buf[..len]).into_owned()
+}
+
+/// Pick the output to dock on when none is specified.
+///
+/// This is the primary output, or the first one if there is no primary.
+/// If there are no outputs at all, an output covering the whole screen
+/// is returned.
+fn default_output(outputs: &[Output], scr_size: (u16, u16)) -> Output {
+    if let Some(o) = outputs.iter().find(|o| o.primary) {
+        return o.clone();
+    }
+
+    if let Some(o) = outputs.first() {
+        return o.clone();
+    }
+
+    Output {
+        name:    String::from("default"),
+        x:       0,
+        y:       0,
+        w:       scr_size.0,
+        h:       scr_size.1,
+        mm_w:    0,
+        mm_h:    0,
+        primary: true,
+    }
+}
+
 pub struct XCB {
 
     conn:     Arc<Connection>,
@@ -39,27 +319,59 @@ pub struct XCB {
 
     size:     (u16, u16), // (w, h)
     pos:      (i16, i16), // (x, y)
+    offset:   (i16, i16), // (x, y) relative to the output
     scr_size: (u16, u16),
-    bottom:   bool,
-
-    click_fn: Arc<Mutex<Box<Fn(i16, i16, u8) + Sync + Send>>>,
+    output:   Output,
+    explicit: bool, // whether the output was chosen by the user
+    edge:     Edge,
+    forced:   bool, // whether the window bypasses the window manager
+    randr_base: Option<u8>, // first RandR event code, if available
+
+    // Transparency without a compositor: the window shows the slice of the
+    // wallpaper under it, with the bar composited over it in `outpix`.
+    cm_atom:    Atom,   // _NET_WM_CM_S<n>
+    manager:    Atom,   // MANAGER, announcing new selection owners
+    cm_owner:   Window, // owner of cm_atom, or WINDOW_NONE
+    rootpix:    Option<(Pixmap, u16, u16)>, // wallpaper and its size
+    pmap_atoms: Vec<Atom>, // ROOT_PIXMAP_ATOMS
+    outpix:     Pixmap,
+
+    tray:       Option<Tray>,
+
+    // Popup window, created when first opened
+    popup:      Window,
+    popup_pix:  Pixmap,
+    popup_size: Option<(u16, u16)>, // None until created
+    grabbed:    bool, // whether a menu has the keyboard and the pointer
+    cursor_font: Option<Font>, // opened when first needed
+    cursors:    HashMap<u16, Cursor>, // by glyph
+    popup_events: Vec<PopupEvent>,
+
+    event_fn:  Arc<Mutex<Box<Fn(Event) + Sync + Send>>>,
+    screen_fn: Arc<Mutex<Box<Fn() + Sync + Send>>>,
 }
 
 impl XCB {
-    pub fn new() -> XCB {
+    /// Connect to the X server in `$DISPLAY` and create the window.
+    pub fn new() -> Result<XCB> {
 
         // Create XCB struct to return
         let (conn, scr_num) = {
-            let (conn, scr_num) = Connection::connect(None).unwrap();
+            let (conn, scr_num) = Connection::connect(None)?;
             (Arc::new(conn), scr_num)
         };
         let win = conn.generate_id();
         let gc = conn.generate_id(); // The GC is created later
         let colour = conn.generate_id();
-        let click_fn: Arc<Mutex<Box<Fn(i16, i16, u8) + Sync + Send>>> =
-            Arc::new(Mutex::new(Box::new(|_, _, _| {} // Placeholder closure
+        let event_fn: Arc<Mutex<Box<Fn(Event) + Sync + Send>>> =
+            Arc::new(Mutex::new(Box::new(|_| {} // Placeholder closure
         )));
+        let screen_fn: Arc<Mutex<Box<Fn() + Sync + Send>>> =
+            Arc::new(Mutex::new(Box::new(|| {})));
         let bufpix = conn.generate_id(); // Pixmap created later
+        let outpix = conn.generate_id();
+        let popup = conn.generate_id();
+        let popup_pix = conn.generate_id();
         let size = (1u16, 1u16); // default size
 
         let root;
@@ -67,10 +379,11 @@ impl XCB {
         let depth;
         let mut scr_size = (0u16, 0u16);
         {
-            let screen = conn.get_setup()
-                             .roots()
-                             .nth(scr_num as usize)
-                             .unwrap();
+            let setup = conn.get_setup();
+            let screen = setup.roots()
+                              .nth(scr_num as usize)
+                              .ok_or_else(|| Error::Connection(
+                                  format!("No screen {}", scr_num)))?;
             scr_size.0 = screen.width_in_pixels();
             scr_size.1 = screen.height_in_pixels();
             root = screen.root();
@@ -78,9 +391,17 @@ impl XCB {
             let (v, d) = get_visualid_from_depth(screen, 32);
             visual = v;
             depth = d;
+
+            // Neither a compositor nor pseudo-transparency can help then
+            if depth != 32 {
+                NO_ALPHA.call_once(|| eprintln!(
+                    "No 32-bit visual, translucent colours are opaque"));
+            }
         }
 
-        let x = XCB {
+        let output = default_output(&query_outputs(&conn, root), scr_size);
+
+        let mut x = XCB {
             conn,
             scr_num,
             win,
@@ -92,9 +413,29 @@ impl XCB {
             depth,
             size,
             pos:         (0, 0),
+            offset:      (0, 0),
             scr_size,
-            bottom:      false,
-            click_fn,
+            output,
+            explicit:    false,
+            edge:        Edge::Top,
+            forced:      false,
+            randr_base:  None,
+            cm_atom:     ATOM_NONE,
+            manager:     ATOM_NONE,
+            cm_owner:    WINDOW_NONE,
+            rootpix:     None,
+            pmap_atoms:  Vec::new(),
+            outpix,
+            tray:        None,
+            popup,
+            popup_pix,
+            popup_size:  None,
+            grabbed:     false,
+            cursor_font: None,
+            cursors:     HashMap::new(),
+            popup_events: Vec::new(),
+            event_fn,
+            screen_fn,
         };
 
         // Create the window
@@ -102,10 +443,10 @@ impl XCB {
         create_colormap(&*x.conn, COLORMAP_ALLOC_NONE as u8,
                         x.colour, x.root,
                         x.visual)
-            .request_check().unwrap();
+            .request_check()?;
 
         let values = [
-            (CW_EVENT_MASK, EVENT_MASK_BUTTON_PRESS | EVENT_MASK_EXPOSURE),
+            (CW_EVENT_MASK, WINDOW_EVENTS),
             (CW_BACK_PIXEL, 0),
             (CW_COLORMAP, x.colour),
             (CW_BORDER_PIXEL, 0),
@@ -121,60 +462,48 @@ impl XCB {
                       WINDOW_CLASS_INPUT_OUTPUT as u16,
                       x.visual,
                       &values)
-            .request_check().unwrap();
+            .request_check()?;
 
-       let title = "lemonade";
-        change_property(&*x.conn, xcb::PROP_MODE_REPLACE as u8, x.win, 
-            xcb::ATOM_WM_NAME, xcb::ATOM_STRING, 8, title.as_bytes());
+        x.set_identity()?;
 
         create_gc(&*x.conn, x.gc, x.win, &[]);
         create_pixmap(&*x.conn, x.depth, x.bufpix,
                       x.win, x.size.0, x.size.1);
+        create_pixmap(&*x.conn, x.depth, x.outpix,
+                      x.win, x.size.0, x.size.1);
 
-        // Create event-monitoring thread
-        let conn = x.conn.clone();
-        let click_fn = x.click_fn.clone();
-        let win = x.win;
-        let bufpix = x.bufpix;
-        let gc = x.gc;
-        thread::spawn(move || {
-            while let Some(e) = conn.wait_for_event() {
-                match e.response_type() as u8 {
-                    BUTTON_PRESS => {
-                        let e: &ButtonPressEvent = unsafe {
-                            cast_event(&e)
-                        };
-
-                        let (x, y) = (e.event_x(), e.event_y());
-                        let b = e.detail();
-                        let f = click_fn.lock().unwrap();
-
-                        f(x, y, b);
-                    }
-
-                    EXPOSE => {
-                        let e: &ExposeEvent = unsafe {
-                            cast_event(&e)
-                        };
-
-                        let w = e.width();
-                        let h = e.height();
-                        let x = e.x() as i16;
-                        let y = e.y() as i16;
-                        copy_area(&*conn, bufpix, win, gc,
-                                  x, y, x, y, w, h);
-
-                        conn.flush();
-                    }
+        // Listen for changes of the screen layout. The root window gets
+        // a ConfigureNotify when the screen is resized, and RandR tells
+        // us about outputs being plugged in or out. Wallpaper setters
+        // change properties of the root window, and compositors announce
+        // themselves with a MANAGER client message sent to it.
+        change_window_attributes(&*x.conn, x.root, &[
+                (CW_EVENT_MASK, EVENT_MASK_STRUCTURE_NOTIFY
+                              | EVENT_MASK_PROPERTY_CHANGE),
+        ]);
+
+        x.cm_atom = x.get_atom(&format!("_NET_WM_CM_S{}", x.scr_num))?;
+        x.manager = x.get_atom("MANAGER")?;
+        for name in &ROOT_PIXMAP_ATOMS {
+            let atom = x.get_atom(name)?;
+            x.pmap_atoms.push(atom);
+        }
+        x.update_compositor();
+        x.update_root_pixmap();
 
-                    _ => {}
-                }
-            }
+        x.randr_base = match x.conn.get_extension_data(randr::id()) {
+            Some(ref ext) if ext.present() => Some(ext.first_event()),
+            _ => None,
+        };
 
-            println!("ERROR");
-        });
+        if x.randr_base.is_some() {
+            randr::select_input(&*x.conn, x.root,
+                                (randr::NOTIFY_MASK_SCREEN_CHANGE
+                               | randr::NOTIFY_MASK_CRTC_CHANGE
+                               | randr::NOTIFY_MASK_OUTPUT_CHANGE) as u16);
+        }
 
-        return x;
+        Ok(x)
     }
 
     fn map_window(&self) {
@@ -185,73 +514,209 @@ impl XCB {
         unmap_window(&self.conn, self.win);
     }
 
-    fn reposition_window(&mut self) {
+    /// Move the window to its place on the output and update the struts.
+    ///
+    /// The struts are relative to the edges of the root window, so the
+    /// position of the output is taken into account.
+    fn reposition_window(&mut self) -> Result<()> {
         self.unmap_window();
 
-        let mut data: [i16; 12] = [
-            0, 0, 0, 0, // left, right, top, bottom
-            0, 0, // left offset
-            0, 0, // right offset
-            0, 0, // top offset
-            0, 0, // bottom offset
-        ];
-
-        let curr_x = self.pos.0;
-        let (xb, xe) = (curr_x, curr_x + self.size.0 as i16);
+        let (xpos, ypos) = place(&self.output, self.offset, self.size,
+                                 self.edge);
+        self.set_pos(xpos as u16, ypos as u16)?;
 
-        let ypos;
-        if self.bottom {
-            ypos = self.scr_size.1 as i16 - self.size.1 as i16;
+        let struts = Struts::new(self.pos, self.size, self.scr_size,
+                                 self.edge);
 
-            data[2]  = 0; // top offset
-            data[3]  = self.size.1 as i16;
-            data[8]  = 0;  data[9]  = 0;
-            data[10] = xb; data[11] = xe;
-        } else {
-            ypos = 0;
-
-            data[2]  = self.size.1 as i16;
-            data[3]  = 0; // bottom offset
-            data[8]  = xb; data[9]  = xe;
-            data[10] = 0;  data[11] = 0;
-        }
-
-        self.set_pos(curr_x as u16, ypos as u16);
+        change_property(&self.conn,
+                        PROP_MODE_REPLACE as u8,
+                        self.win,
+                        self.get_atom("_NET_WM_STRUT_PARTIAL")?,
+                        ATOM_CARDINAL,
+                        32,
+                        &struts.partial);
 
         change_property(&self.conn,
                         PROP_MODE_REPLACE as u8,
                         self.win,
-                        self.get_atom("_NET_WM_STRUT_PARTIAL"),
-                        ATOM_ATOM,
-                        16,
-                        &data);
+                        self.get_atom("_NET_WM_STRUT")?,
+                        ATOM_CARDINAL,
+                        32,
+                        &struts.legacy())
+            .request_check()?;
 
         self.map_window();
+        Ok(())
+    }
+
+    /// Set the properties identifying the window, which must be there
+    /// before it is mapped: its default name and class, the process
+    /// owning it, and the state keeping it on every desktop and above
+    /// other windows.
+    fn set_identity(&mut self) -> Result<()> {
+        self.set_name("lemonade")?;
+        self.set_class("lemonade", "Lemonade")?;
+
+        // _NET_WM_PID is only meaningful along with the host
+        let pid = [process::id()];
+        change_property(&self.conn, PROP_MODE_REPLACE as u8, self.win,
+                        self.get_atom("_NET_WM_PID")?, ATOM_CARDINAL, 32,
+                        &pid);
+
+        let host = hostname();
+        change_property(&self.conn, PROP_MODE_REPLACE as u8, self.win,
+                        ATOM_WM_CLIENT_MACHINE, ATOM_STRING, 8,
+                        host.as_bytes());
+
+        let desktop = [0xFFFFFFFFu32]; // all desktops
+        change_property(&self.conn, PROP_MODE_REPLACE as u8, self.win,
+                        self.get_atom("_NET_WM_DESKTOP")?, ATOM_CARDINAL, 32,
+                        &desktop);
+
+        let state = [
+            self.get_atom("_NET_WM_STATE_STICKY")?,
+            self.get_atom("_NET_WM_STATE_ABOVE")?,
+        ];
+        change_property(&self.conn, PROP_MODE_REPLACE as u8, self.win,
+                        self.get_atom("_NET_WM_STATE")?, ATOM_ATOM, 32,
+                        &state)
+            .request_check()?;
+
+        Ok(())
+    }
+
+    /// Look for a compositor, which owns the `_NET_WM_CM_S<n>` selection
+    /// of the screen while it runs.
+    ///
+    /// Compositors destroy the owner window when they exit, so it is
+    /// watched to know when to fall back to pseudo-transparency.
+    fn update_compositor(&mut self) {
+        self.cm_owner = get_selection_owner(&self.conn, self.cm_atom)
+            .get_reply()
+            .map(|r| r.owner())
+            .unwrap_or(WINDOW_NONE);
+
+        if self.cm_owner != WINDOW_NONE {
+            change_window_attributes(&*self.conn, self.cm_owner, &[
+                    (CW_EVENT_MASK, EVENT_MASK_STRUCTURE_NOTIFY),
+            ]);
+        }
+    }
+
+    /// Find the pixmap of the wallpaper, as set by the wallpaper setter.
+    ///
+    /// The pixmap is left out if it is gone, which happens when the
+    /// setter does not keep it around after exiting.
+    fn update_root_pixmap(&mut self) {
+        self.rootpix = None;
+
+        for &atom in &self.pmap_atoms {
+            let pix = match get_property(&self.conn, false, self.root, atom,
+                                         ATOM_PIXMAP, 0, 1).get_reply() {
+                Ok(ref r) if r.value_len() == 1 => r.value::<u32>()[0],
+                _ => continue,
+            };
+
+            if let Ok(g) = get_geometry(&self.conn, pix).get_reply() {
+                self.rootpix = Some((pix, g.width(), g.height()));
+                return;
+            }
+        }
+    }
+
+    /// Whether the bar is composited over the wallpaper by itself.
+    ///
+    /// Only the alpha of a 32-bit visual can be used for this, and
+    /// compositors handle it on their own.
+    fn pseudo_transparent(&self) -> bool {
+        self.depth == 32 && self.cm_owner == WINDOW_NONE
+            && self.rootpix.is_some()
+    }
+
+    /// Create a cairo surface for a pixmap of the given visual and size.
+    fn pixmap_surface(&self, pix: Pixmap, visual: Visualid,
+                      size: (u16, u16)) -> Result<cairo::Surface> {
+        let cr_conn = unsafe {
+            cairo::XCBConnection::from_raw_none(
+                self.conn.get_raw_conn() as *mut cairo_sys::xcb_connection_t)
+        };
+
+        let mut visual = self.get_visual(visual)?;
+        let cr_visual = unsafe {
+            cairo::XCBVisualType::from_raw_none(
+                &mut visual.base as *mut ffi::xcb_visualtype_t
+                                 as *mut cairo_sys::xcb_visualtype_t)
+        };
+
+        Ok(cairo::Surface::create(
+            &cr_conn, &cairo::XCBDrawable(pix), &cr_visual,
+            size.0 as i32, size.1 as i32))
+    }
+
+    /// Paint the slice of the wallpaper under the window into `outpix`,
+    /// with the bar over it, in the area at `pos` of the given size.
+    fn composite_root(&self, pos: (i16, i16), size: (u16, u16))
+                      -> Result<()> {
+        let (pix, w, h) = match self.rootpix {
+            Some(p) => p,
+            None    => return Ok(()),
+        };
+
+        let root_visual = match self.get_screen() {
+            Some(scr) => scr.root_visual(),
+            None      => return Ok(()),
+        };
+
+        let root = self.pixmap_surface(pix, root_visual, (w, h))?;
+        let bar = self.pixmap_surface(self.bufpix, self.visual, self.size)?;
+        let out = self.pixmap_surface(self.outpix, self.visual, self.size)?;
+
+        let cr = cairo::Context::new(&out);
+        cr.rectangle(pos.0 as f64, pos.1 as f64,
+                     size.0 as f64, size.1 as f64);
+        cr.clip();
+
+        cr.set_operator(cairo::Operator::Source);
+        cr.set_source_surface(&root, -self.pos.0 as f64, -self.pos.1 as f64);
+        cr.paint();
+
+        cr.set_operator(cairo::Operator::Over);
+        cr.set_source_surface(&bar, 0.0, 0.0);
+        cr.paint();
+
+        Ok(())
+    }
+
+    /// Get the pixmap the window shows.
+    fn front(&self) -> Pixmap {
+        if self.pseudo_transparent() { self.outpix } else { self.bufpix }
     }
 
-    fn get_atom(&self, name: &str) -> Atom {
+    fn get_atom(&self, name: &str) -> Result<Atom> {
         let atom = intern_atom(&self.conn, false, name);
 
-        atom.get_reply().unwrap().atom()
+        Ok(atom.get_reply()?.atom())
     }
 
-    fn get_screen(&self) -> Screen {
+    fn get_screen(&self) -> Option<Screen> {
         let setup = self.conn.get_setup();
-        let screen = setup.roots().nth(self.scr_num as usize).unwrap();
+        let screen = setup.roots().nth(self.scr_num as usize);
 
         return screen;
     }
 
-    fn get_visual(&self) -> Visualtype {
-        for d in self.get_screen().allowed_depths() {
-            for v in d.visuals() {
-                if v.visual_id() == self.visual {
-                    return v;
+    fn get_visual(&self, id: Visualid) -> Result<Visualtype> {
+        if let Some(scr) = self.get_screen() {
+            for d in scr.allowed_depths() {
+                for v in d.visuals() {
+                    if v.visual_id() == id {
+                        return Ok(v);
+                    }
                 }
             }
         }
 
-        panic!("Failed to find visual type");
+        Err(Error::Unsupported(String::from("Failed to find visual type")))
     }
 
     /// Set a new size for the window.
@@ -259,12 +724,15 @@ impl XCB {
     /// Note: This clears the buffer, so make sure to draw
     /// after setting the size and not before. Else, the
     /// drawn image is lost.
-    fn set_size(&mut self, w: u16, h: u16) {
+    fn set_size(&mut self, w: u16, h: u16) -> Result<()> {
 
-        // Update the pixmap to match new size
+        // Update the pixmaps to match new size
         free_pixmap(&self.conn, self.bufpix);
         create_pixmap(&self.conn, self.depth, self.bufpix,
                       self.win, w, h);
+        free_pixmap(&self.conn, self.outpix);
+        create_pixmap(&self.conn, self.depth, self.outpix,
+                      self.win, w, h);
 
         // Clear the new pixmap
         change_gc(&*self.conn, self.gc, &[(GC_FUNCTION, GX_CLEAR)]);
@@ -276,114 +744,564 @@ impl XCB {
         configure_window(&*self.conn, self.win, &[
                 (CONFIG_WINDOW_WIDTH as u16, w as u32),
                 (CONFIG_WINDOW_HEIGHT as u16, h as u32),
-        ]).request_check()
-          .unwrap();
+        ]).request_check()?;
 
         self.size = (w, h);
+        Ok(())
+    }
+
+    /// Create the popup window, which bypasses the window manager like
+    /// menus and tooltips do.
+    fn create_popup(&mut self) -> Result<()> {
+        let values = [
+            (CW_BACK_PIXEL, 0),
+            (CW_BORDER_PIXEL, 0),
+            (CW_OVERRIDE_REDIRECT, 1),
+            (CW_EVENT_MASK, EVENT_MASK_EXPOSURE),
+            (CW_COLORMAP, self.colour),
+        ];
+
+        create_window(&*self.conn, self.depth, self.popup, self.root,
+                      0, 0, 1, 1, 0, WINDOW_CLASS_INPUT_OUTPUT as u16,
+                      self.visual, &values)
+            .request_check()?;
+
+        create_pixmap(&*self.conn, self.depth, self.popup_pix,
+                      self.popup, 1, 1);
+        self.popup_size = Some((1, 1));
+
+        Ok(())
+    }
+
+    /// Get the cursor of a glyph of the cursor font by its name, creating
+    /// it the first time.
+    fn load_cursor(&mut self, name: &str) -> Result<Cursor> {
+        let glyph = match CURSORS.iter().find(|&&(n, _)| n == name) {
+            Some(&(_, g)) => g,
+            None => return Err(Error::Unsupported(
+                format!("Unknown cursor {}", name))),
+        };
+
+        if let Some(&c) = self.cursors.get(&glyph) {
+            return Ok(c);
+        }
+
+        let font = match self.cursor_font {
+            Some(f) => f,
+            None    => {
+                let f = self.conn.generate_id();
+                open_font_checked(&*self.conn, f, "cursor")
+                    .request_check()?;
+                self.cursor_font = Some(f);
+                f
+            }
+        };
+
+        // Black on white, like the default cursor
+        let c = self.conn.generate_id();
+        create_glyph_cursor(&*self.conn, c, font, font, glyph, glyph + 1,
+                            0, 0, 0, 0xffff, 0xffff, 0xffff)
+            .request_check()?;
+
+        self.cursors.insert(glyph, c);
+        Ok(c)
+    }
+
+    /// Grab the keyboard and the pointer for a menu, so that it can be
+    /// used with the keyboard and closed by clicking elsewhere.
+    fn grab_input(&mut self) -> Result<()> {
+        let mask = EVENT_MASK_BUTTON_PRESS
+                 | EVENT_MASK_BUTTON_RELEASE
+                 | EVENT_MASK_POINTER_MOTION;
+
+        let pointer = grab_pointer(&*self.conn, false, self.popup,
+                                   mask as u16, GRAB_MODE_ASYNC as u8,
+                                   GRAB_MODE_ASYNC as u8, WINDOW_NONE,
+                                   CURSOR_NONE, TIME_CURRENT_TIME)
+            .get_reply()?;
+        if pointer.status() != GRAB_STATUS_SUCCESS as u8 {
+            return Err(Error::Unsupported(
+                String::from("Failed to grab the pointer for a menu")));
+        }
+
+        // The menu still works with the pointer only
+        let _ = grab_keyboard(&*self.conn, false, self.popup,
+                              TIME_CURRENT_TIME, GRAB_MODE_ASYNC as u8,
+                              GRAB_MODE_ASYNC as u8).get_reply();
+
+        self.grabbed = true;
+        Ok(())
+    }
+
+    fn ungrab_input(&mut self) {
+        ungrab_keyboard(&*self.conn, TIME_CURRENT_TIME);
+        ungrab_pointer(&*self.conn, TIME_CURRENT_TIME);
+        self.popup_events.clear();
+        self.grabbed = false;
     }
 
     /// Set the internal position value.
     ///
     /// Cannot move the window if it is docked. The `reposition_window` method
     /// must be used if it is docked.
-    fn set_pos(&mut self, x: u16, y: u16) {
+    fn set_pos(&mut self, x: u16, y: u16) -> Result<()> {
         configure_window(&self.conn, self.win, &[
                 (CONFIG_WINDOW_X as u16, x as u32),
                 (CONFIG_WINDOW_Y as u16, y as u32),
-        ]).request_check()
-          .unwrap();
+        ]).request_check()?;
 
         self.pos = (x as i16, y as i16);
+        Ok(())
     }
 }
 
 
 impl Dock for XCB {
-    fn create_surface(&self) -> cairo::Surface {
-
-        // Prepare cairo variables
-        let cr_conn = unsafe {
-            cairo::XCBConnection::from_raw_none(
-                self.conn.get_raw_conn() as *mut cairo_sys::xcb_connection_t)
-        };
-
-        let cr_draw = cairo::XCBDrawable(self.bufpix);
-
-        let cr_visual = unsafe {
-            cairo::XCBVisualType::from_raw_none(
-                &mut self.get_visual().base as *mut ffi::xcb_visualtype_t
-                                            as *mut cairo_sys::xcb_visualtype_t)
-        };
-
-        // Create the surface using previous variables
-        return cairo::Surface::create(
-            &cr_conn, &cr_draw, &cr_visual,
-            self.size.0 as i32, self.size.1 as i32);
+    fn create_surface(&self) -> Result<cairo::Surface> {
+        self.pixmap_surface(self.bufpix, self.visual, self.size)
     }
 
-    fn dock(&self) {
+    fn dock(&self) -> Result<()> {
         let data = [
-            self.get_atom("_NET_WM_WINDOW_TYPE_DOCK"),
+            self.get_atom("_NET_WM_WINDOW_TYPE_DOCK")?,
         ];
 
         change_property(&self.conn,
                              PROP_MODE_REPLACE as u8,
                              self.win,
-                             self.get_atom("_NET_WM_WINDOW_TYPE"),
+                             self.get_atom("_NET_WM_WINDOW_TYPE")?,
                              xcb::ATOM_ATOM,
                              32,
                              &data)
-            .request_check()
-            .expect("Failed to dock window");
+            .request_check()?;
+
+        Ok(())
+    }
+
+    /// Make the window override-redirect, so that the window manager
+    /// leaves it alone.
+    ///
+    /// This takes effect when the window is mapped again, which moving it
+    /// does. The struts are still published, but the window manager does
+    /// not stack the window anymore, so it raises itself whenever it gets
+    /// obscured.
+    fn force_dock(&mut self, force: bool) -> Result<()> {
+        let events = if force {
+            WINDOW_EVENTS | EVENT_MASK_VISIBILITY_CHANGE
+        } else {
+            WINDOW_EVENTS
+        };
+
+        change_window_attributes(&*self.conn, self.win, &[
+                (CW_OVERRIDE_REDIRECT, force as u32),
+                (CW_EVENT_MASK, events),
+        ]).request_check()?;
+
+        self.forced = force;
+        self.reposition_window()
     }
 
-    fn top(&mut self) {
-        self.bottom = false;
-        self.reposition_window();
+    fn top(&mut self) -> Result<()> {
+        self.edge = Edge::Top;
+        self.reposition_window()
     }
 
-    fn bottom(&mut self) {
-        self.bottom = true;
-        self.reposition_window();
+    fn bottom(&mut self) -> Result<()> {
+        self.edge = Edge::Bottom;
+        self.reposition_window()
     }
 
-    fn set_size(&mut self, w: u16, h: u16) {
-        self.set_size(w, h);
+    fn left(&mut self) -> Result<()> {
+        self.edge = Edge::Left;
+        self.reposition_window()
     }
 
-    fn set_offset(&mut self, x: u16, y: u16) {
-        if self.bottom {
-            let screen_height = self.scr_size.1;
-            self.set_pos(x, screen_height - y);
-        } else {
-            self.set_pos(x, y);
-        }
+    fn right(&mut self) -> Result<()> {
+        self.edge = Edge::Right;
+        self.reposition_window()
+    }
+
+    fn set_size(&mut self, w: u16, h: u16) -> Result<()> {
+        self.set_size(w, h)
+    }
 
-        self.reposition_window();
+    fn set_offset(&mut self, x: u16, y: u16) -> Result<()> {
+        self.offset = (x as i16, y as i16);
+        self.reposition_window()
     }
 
+    /// Get the size of the output the bar is docked on.
     fn get_screen_size(&self) -> (u16, u16) {
-        (self.scr_size.0, self.scr_size.1)
+        (self.output.w, self.output.h)
+    }
+
+    /// Get the scale from `Xft.dpi`, which applies to every output, or
+    /// else from the physical size of the output.
+    fn get_scale(&self) -> f64 {
+        let res = get_property(&self.conn, false, self.root,
+                               ATOM_RESOURCE_MANAGER, ATOM_STRING,
+                               0, u32::max_value()).get_reply();
+
+        let dpi = res.ok().and_then(|r| {
+            xft_dpi(&String::from_utf8_lossy(r.value::<u8>()))
+        });
+
+        match dpi {
+            Some(dpi) => dpi / BASE_DPI,
+            None      => self.output.scale(),
+        }
+    }
+
+    fn get_outputs(&self) -> Vec<Output> {
+        query_outputs(&self.conn, self.root)
+    }
+
+    fn set_output(&mut self, name: &str) -> Result<()> {
+        match self.get_outputs().into_iter().find(|o| o.name == name) {
+            Some(o) => self.output = o,
+            None    => return Err(Error::NoOutput(String::from(name))),
+        }
+
+        self.explicit = true;
+        self.reposition_window()
+    }
+
+    /// Set both `WM_NAME` and `_NET_WM_NAME`, the latter being UTF-8.
+    fn set_name(&mut self, name: &str) -> Result<()> {
+        change_property(&*self.conn, PROP_MODE_REPLACE as u8, self.win,
+                        ATOM_WM_NAME, ATOM_STRING, 8, name.as_bytes());
+
+        change_property(&*self.conn, PROP_MODE_REPLACE as u8, self.win,
+                        self.get_atom("_NET_WM_NAME")?,
+                        self.get_atom("UTF8_STRING")?, 8, name.as_bytes())
+            .request_check()?;
+
+        Ok(())
+    }
+
+    /// Cursors are made from the core cursor font. Loading them from
+    /// Xcursor themes needs libxcursor, so the server's theme is used.
+    fn set_cursor(&mut self, name: Option<&str>) -> Result<()> {
+        let cursor = match name {
+            Some(name) => self.load_cursor(name)?,
+            None       => CURSOR_NONE,
+        };
+
+        change_window_attributes(&*self.conn, self.win, &[
+                (CW_CURSOR, cursor),
+        ]);
+        self.conn.flush();
+        Ok(())
+    }
+
+    /// Set `WM_CLASS`, which holds both names separated by nul bytes.
+    fn set_class(&mut self, instance: &str, class: &str) -> Result<()> {
+        let data = format!("{}\0{}\0", instance, class);
+
+        change_property(&*self.conn, PROP_MODE_REPLACE as u8, self.win,
+                        ATOM_WM_CLASS, ATOM_STRING, 8, data.as_bytes())
+            .request_check()?;
+
+        Ok(())
+    }
+
+    fn enable_tray(&mut self, vertical: bool) -> Result<()> {
+        if self.tray.is_none() {
+            self.tray = Some(Tray::new(self.conn.clone(), self.scr_num,
+                                       self.root, self.win, self.visual,
+                                       vertical)?);
+        }
+
+        Ok(())
+    }
+
+    fn tray_icons(&self) -> usize {
+        self.tray.as_ref().map(|t| t.len()).unwrap_or(0)
+    }
+
+    fn place_tray(&self, pos: Option<(i16, i16)>, size: u16,
+                  vertical: bool) {
+        if let Some(ref t) = self.tray {
+            t.place(pos, size, vertical);
+        }
+    }
+
+    fn open_popup(&mut self, kind: Popup, area: (i16, i16),
+                  size: (u16, u16)) -> Result<cairo::Surface> {
+        if self.popup_size.is_none() {
+            self.create_popup()?;
+        }
+
+        if self.popup_size != Some(size) {
+            free_pixmap(&*self.conn, self.popup_pix);
+            create_pixmap(&*self.conn, self.depth, self.popup_pix,
+                          self.popup, size.0, size.1);
+            self.popup_size = Some(size);
+        }
+
+        let (x, y) = popup_position(&self.output, self.pos, self.size,
+                                    self.edge, kind, area, size);
+
+        // Compositors style popups by their type
+        let kind_atom = match kind {
+            Popup::Tooltip => self.get_atom("_NET_WM_WINDOW_TYPE_TOOLTIP")?,
+            Popup::Menu => self.get_atom("_NET_WM_WINDOW_TYPE_DROPDOWN_MENU")?,
+        };
+        change_property(&*self.conn, PROP_MODE_REPLACE as u8, self.popup,
+                        self.get_atom("_NET_WM_WINDOW_TYPE")?, ATOM_ATOM, 32,
+                        &[kind_atom]);
+
+        configure_window(&*self.conn, self.popup, &[
+                (CONFIG_WINDOW_X as u16, x as u32),
+                (CONFIG_WINDOW_Y as u16, y as u32),
+                (CONFIG_WINDOW_WIDTH as u16, size.0 as u32),
+                (CONFIG_WINDOW_HEIGHT as u16, size.1 as u32),
+                (CONFIG_WINDOW_STACK_MODE as u16, STACK_MODE_ABOVE as u32),
+        ]).request_check()?;
+        map_window(&*self.conn, self.popup);
+
+        match kind {
+            Popup::Menu if !self.grabbed => self.grab_input()?,
+            Popup::Tooltip if self.grabbed => self.ungrab_input(),
+            _ => {}
+        }
+
+        self.pixmap_surface(self.popup_pix, self.visual, size)
+    }
+
+    fn flush_popup(&self) {
+        if let Some((w, h)) = self.popup_size {
+            copy_area(&*self.conn, self.popup_pix, self.popup, self.gc,
+                      0, 0, 0, 0, w, h);
+            self.conn.flush();
+        }
+    }
+
+    fn close_popup(&mut self) {
+        if self.grabbed {
+            self.ungrab_input();
+        }
+
+        if self.popup_size.is_some() {
+            unmap_window(&*self.conn, self.popup);
+            self.conn.flush();
+        }
+    }
+
+    fn popup_events(&mut self) -> Vec<PopupEvent> {
+        mem::replace(&mut self.popup_events, Vec::new())
+    }
+
+    /// Update the screen and output geometry after a change.
+    ///
+    /// If the output was chosen with `set_output` and is no longer
+    /// connected, false is returned and the window is left as is.
+    /// Otherwise the window is moved to the new position of its output.
+    fn refresh_screen(&mut self) -> Result<bool> {
+        if let Ok(g) = get_geometry(&self.conn, self.root).get_reply() {
+            self.scr_size = (g.width(), g.height());
+        }
+
+        let outputs = self.get_outputs();
+        let output = if self.explicit {
+            match outputs.into_iter().find(|o| o.name == self.output.name) {
+                Some(o) => o,
+                None    => return Ok(false),
+            }
+        } else {
+            default_output(&outputs, self.scr_size)
+        };
+
+        self.output = output;
+        self.reposition_window()?;
+
+        Ok(true)
     }
 
+    /// Show what was drawn, over the wallpaper if there is no compositor.
     fn flush(&self) {
-        copy_area(&*self.conn, self.bufpix, self.win, self.gc,
-                  0, 0, 0, 0, self.size.0, self.size.1);
+        self.flush_area((0, 0), self.size);
+    }
+
+    /// Show the area at `pos` of what was drawn. Only that area is
+    /// composited over the wallpaper, as updates flush each item apart.
+    fn flush_area(&self, pos: (i16, i16), size: (u16, u16)) {
+        if self.pseudo_transparent() {
+            if let Err(e) = self.composite_root(pos, size) {
+                eprintln!("{}", e);
+            }
+        }
+
+        copy_area(&*self.conn, self.front(), self.win, self.gc,
+                  pos.0, pos.1, pos.0, pos.1, size.0, size.1);
         self.conn.flush();
     }
 
-    fn click_cb<F>(&mut self, f: F)
-        where F: Fn(i16, i16, u8) + Send + Sync + 'static {
+    fn event_cb<F>(&mut self, f: F)
+        where F: Fn(Event) + Send + Sync + 'static {
+
+        let mut cb = self.event_fn.lock().unwrap();
+        *cb = Box::new(f);
+    }
+
+    fn screen_cb<F>(&mut self, f: F)
+        where F: Fn() + Send + Sync + 'static {
 
-        let mut cb = self.click_fn.lock().unwrap();
+        let mut cb = self.screen_fn.lock().unwrap();
         *cb = Box::new(f);
     }
+
+    fn get_fd(&self) -> RawFd {
+        self.conn.as_raw_fd()
+    }
+
+    fn handle_events(&mut self) -> Result<()> {
+        while let Some(e) = self.conn.poll_for_event() {
+            if let Some(ref mut t) = self.tray {
+                if t.handle_event(&e) {
+                    continue;
+                }
+            }
+
+            match e.response_type() & !0x80 {
+                // A menu gets all the input while it is open
+                BUTTON_PRESS | BUTTON_RELEASE | MOTION_NOTIFY
+                | ENTER_NOTIFY | LEAVE_NOTIFY | KEY_PRESS
+                    if self.grabbed => {
+                    if let Some(e) = menu_event(&self.conn, &e) {
+                        self.popup_events.push(e);
+                    }
+                }
+
+                BUTTON_PRESS | BUTTON_RELEASE | MOTION_NOTIFY
+                | ENTER_NOTIFY | LEAVE_NOTIFY => {
+                    if let Some(e) = pointer_event(&e) {
+                        let f = self.event_fn.lock().unwrap();
+                        f(e);
+                    }
+                }
+
+                EXPOSE => {
+                    let e: &ExposeEvent = unsafe {
+                        cast_event(&e)
+                    };
+
+                    let w = e.width();
+                    let h = e.height();
+                    let x = e.x() as i16;
+                    let y = e.y() as i16;
+
+                    let (pix, win) = if e.window() == self.popup {
+                        (self.popup_pix, self.popup)
+                    } else {
+                        (self.front(), self.win)
+                    };
+                    copy_area(&*self.conn, pix, win, self.gc,
+                              x, y, x, y, w, h);
+                }
+
+                PROPERTY_NOTIFY => {
+                    let e: &PropertyNotifyEvent = unsafe {
+                        cast_event(&e)
+                    };
+
+                    // The wallpaper changed
+                    if e.window() == self.root
+                        && self.pmap_atoms.contains(&e.atom()) {
+                        self.update_root_pixmap();
+                        self.flush();
+                    }
+                }
+
+                CLIENT_MESSAGE => {
+                    let e: &ClientMessageEvent = unsafe {
+                        cast_event(&e)
+                    };
+
+                    // A manager of some selection started, as told to
+                    // the root window by ICCCM. The selection is the
+                    // second value.
+                    if e.window() == self.root && e.type_() == self.manager
+                        && e.data().data32()[1] == self.cm_atom {
+                        self.update_compositor();
+                        self.flush();
+                    }
+                }
+
+                DESTROY_NOTIFY => {
+                    let e: &DestroyNotifyEvent = unsafe {
+                        cast_event(&e)
+                    };
+
+                    // The compositor exited
+                    if e.window() == self.cm_owner {
+                        self.update_compositor();
+                        self.update_root_pixmap();
+                        self.flush();
+                    }
+                }
+
+                VISIBILITY_NOTIFY => {
+                    let e: &VisibilityNotifyEvent = unsafe {
+                        cast_event(&e)
+                    };
+
+                    if self.forced
+                        && e.state() != VISIBILITY_UNOBSCURED as u8 {
+                        configure_window(&*self.conn, self.win, &[
+                            (CONFIG_WINDOW_STACK_MODE as u16,
+                             STACK_MODE_ABOVE as u32),
+                        ]);
+                    }
+                }
+
+                CONFIGURE_NOTIFY => {
+                    let e: &ConfigureNotifyEvent = unsafe {
+                        cast_event(&e)
+                    };
+
+                    if e.window() == self.root {
+                        let f = self.screen_fn.lock().unwrap();
+                        f();
+                    }
+                }
+
+                t => {
+                    // RandR events have a dynamic event code
+                    if let Some(base) = self.randr_base {
+                        if t == base + randr::SCREEN_CHANGE_NOTIFY
+                            || t == base + randr::NOTIFY {
+                            let f = self.screen_fn.lock().unwrap();
+                            f();
+                        }
+                    }
+                }
+            }
+        }
+
+        // Events stop coming once the connection is broken, which can
+        // only be told by asking for its error state.
+        self.conn.flush();
+        self.conn.has_error()
+            .map_err(|e| Error::Disconnected(e.to_string()))
+    }
 }
 
 impl Drop for XCB {
     fn drop(&mut self) {
         free_pixmap(&*self.conn, self.win);
         free_pixmap(&*self.conn, self.bufpix);
+        free_pixmap(&*self.conn, self.outpix);
+        if self.popup_size.is_some() {
+            destroy_window(&*self.conn, self.popup);
+            free_pixmap(&*self.conn, self.popup_pix);
+        }
+        for &c in self.cursors.values() {
+            free_cursor(&*self.conn, c);
+        }
+        if let Some(f) = self.cursor_font {
+            close_font(&*self.conn, f);
+        }
         free_gc(&*self.conn, self.gc);
         free_colormap(&*self.conn, self.colour);
     }
@@ -0,0 +1,57 @@
+//! Fixtures shared by the tests, on headless bars.
+//!
+//! Test crates declare `mod lemon` for the parser, then `mod common`.
+
+#![allow(dead_code)]
+
+use std::sync::{Arc, Mutex};
+
+use lemonade::Bar;
+use lemonade::window::{Dock, Event, Headless, Modifiers};
+use lemon::LemonParser;
+
+pub const RED:   u32 = 0xffff0000;
+pub const GREEN: u32 = 0xff00ff00;
+pub const BLUE:  u32 = 0xff0000ff;
+
+/// Create a bar on a 400x600 screen, showing nothing yet.
+pub fn headless() -> Bar<Headless> {
+    Bar::with_headless(400, 600).unwrap()
+}
+
+/// Draw `line` on the bar.
+pub fn show(bar: &mut Bar<Headless>, line: &str) {
+    bar.set_fmt(LemonParser::new().parse(line));
+    bar.draw();
+}
+
+/// Create a bar showing `line`.
+pub fn bar(line: &str) -> Bar<Headless> {
+    let mut bar = headless();
+    show(&mut bar, line);
+    bar
+}
+
+/// Get the commands run by the bar, from clicks and menus.
+pub fn commands<T: Dock>(bar: &mut Bar<T>) -> Arc<Mutex<Vec<String>>> {
+    let cmds = Arc::new(Mutex::new(Vec::new()));
+    let c = cmds.clone();
+    bar.on_command(move |click| {
+        c.lock().unwrap().push(click.cmd.to_string());
+    });
+    cmds
+}
+
+/// Move the pointer to `(x, y)` over the bar.
+pub fn hover(bar: &mut Bar<Headless>, x: i16, y: i16) {
+    let mods = Modifiers::default();
+    bar.window().send_event(Event::Motion { x, y, mods, time: 0 });
+    bar.handle_events().unwrap();
+}
+
+/// Move the pointer out of the bar.
+pub fn leave(bar: &mut Bar<Headless>) {
+    let mods = Modifiers::default();
+    bar.window().send_event(Event::Leave { x: 0, y: 0, mods, time: 0 });
+    bar.handle_events().unwrap();
+}
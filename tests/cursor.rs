@@ -0,0 +1,53 @@
+//! Tests of the cursor shown over regions, a hand over clickable ones
+//! unless `%{C}` chose another.
+
+extern crate lemonade;
+
+#[path = "../src/bin/lemon.rs"]
+#[allow(dead_code)]
+mod lemon;
+mod common;
+
+use common::{bar, hover, leave};
+
+#[test]
+fn hand_over_clickable_areas() {
+    let mut bar = bar("%{O50}%{A:cmd:}%{O50}%{A}%{M:a:b:}%{O50}%{M}");
+    assert_eq!(bar.window().get_cursor(), None);
+
+    hover(&mut bar, 75, 10);
+    assert_eq!(bar.window().get_cursor(), Some("pointer"));
+
+    hover(&mut bar, 25, 10);
+    assert_eq!(bar.window().get_cursor(), None);
+
+    // Menus are clickable too
+    hover(&mut bar, 125, 10);
+    assert_eq!(bar.window().get_cursor(), Some("pointer"));
+}
+
+#[test]
+fn chosen_by_the_region() {
+    let mut bar = bar(concat!("%{A:set:}%{Csb_h_double_arrow}%{O50}%{C}",
+                              "%{O50}%{A}%{Cxterm}%{O50}%{C}"));
+
+    hover(&mut bar, 25, 10);
+    assert_eq!(bar.window().get_cursor(), Some("sb_h_double_arrow"));
+
+    // Still a hand outside, within the area
+    hover(&mut bar, 75, 10);
+    assert_eq!(bar.window().get_cursor(), Some("pointer"));
+
+    hover(&mut bar, 125, 10);
+    assert_eq!(bar.window().get_cursor(), Some("xterm"));
+}
+
+#[test]
+fn default_on_leave() {
+    let mut bar = bar("%{A:cmd:}%{O50}%{A}");
+    hover(&mut bar, 25, 10);
+    assert_eq!(bar.window().get_cursor(), Some("pointer"));
+
+    leave(&mut bar);
+    assert_eq!(bar.window().get_cursor(), None);
+}
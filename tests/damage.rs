@@ -0,0 +1,138 @@
+//! Tests of updates, which only redraw the items that changed.
+
+extern crate lemonade;
+
+#[path = "../src/bin/lemon.rs"]
+#[allow(dead_code)]
+mod lemon;
+mod common;
+
+use lemonade::{Bar, VerticalText};
+use lemonade::format::{FormatItem, Text};
+use lemonade::window::{Dock, Edge, Headless};
+use lemon::LemonParser;
+use common::{commands, RED, GREEN, BLUE};
+
+/// Create a bar showing `line`, from its first update.
+fn bar(line: &str) -> Bar<Headless> {
+    let mut bar = common::headless();
+    bar.update(LemonParser::new().parse(line));
+    bar
+}
+
+/// Update the bar with `line`, and get the last area copied to the
+/// window, if any was.
+fn update(bar: &mut Bar<Headless>, line: &str)
+          -> Option<((i16, i16), (u16, u16))> {
+    bar.window_mut().flush();
+    let full = bar.window().get_flushed();
+
+    bar.update(LemonParser::new().parse(line));
+    let flushed = bar.window().get_flushed();
+    if flushed == full { None } else { flushed }
+}
+
+#[test]
+fn first_update_draws_everything() {
+    let bar = bar("%{B#f00}%{O50}");
+    assert_eq!(bar.window().get_flushed(), Some(((0, 0), (400, 25))));
+    assert_eq!(bar.window().get_pixel(25, 5), RED);
+}
+
+#[test]
+fn only_changed_items_are_drawn() {
+    let mut bar = bar("%{B#f00}%{O50}%{B#0f0}%{O50}");
+
+    let area = update(&mut bar, "%{B#f00}%{O50}%{B#00f}%{O50}");
+    assert_eq!(area, Some(((50, 0), (51, 25))));
+    assert_eq!(bar.window().get_pixel(25, 5), RED);
+    assert_eq!(bar.window().get_pixel(75, 5), BLUE);
+}
+
+#[test]
+fn unchanged_items_are_not_drawn() {
+    let line = "%{B#f00}%{O50}%{B#0f0}%{O50}";
+    let mut bar = bar(line);
+
+    assert_eq!(update(&mut bar, line), None);
+    assert_eq!(bar.window().get_pixel(75, 5), GREEN);
+}
+
+#[test]
+fn moved_items_are_drawn() {
+    let mut bar = bar("%{B#f00}%{O50}%{B#0f0}%{O50}");
+
+    update(&mut bar, "%{B#f00}%{O60}%{B#0f0}%{O50}");
+    assert_eq!(bar.window().get_pixel(55, 5), RED);
+    assert_eq!(bar.window().get_pixel(105, 5), GREEN);
+}
+
+#[test]
+fn areas_follow_updates() {
+    let mut bar = bar("%{A:a:}%{O50}%{A}");
+
+    let cmds = commands(&mut bar);
+
+    update(&mut bar, "%{O50}%{A:b:}%{O50}%{A}");
+    bar.window().click(25, 10, 1);
+    bar.window().click(75, 10, 1);
+    bar.handle_events().unwrap();
+    assert_eq!(*cmds.lock().unwrap(), vec!["b"]);
+}
+
+#[test]
+fn everything_is_drawn_after_resizing() {
+    let line = "%{B#f00}%{O50}";
+    let mut bar = bar(line);
+    bar.set_size(300, 20).unwrap();
+
+    bar.update(LemonParser::new().parse(line));
+    assert_eq!(bar.window().get_flushed(), Some(((0, 0), (300, 20))));
+    assert_eq!(bar.window().get_pixel(25, 5), RED);
+}
+
+#[test]
+fn text_is_shaped_again_when_stacked() {
+    let line = "%{A:a:}abcdef%{A}";
+    let mut bar = bar(line);
+    bar.set_edge(Edge::Left).unwrap();
+    bar.update(LemonParser::new().parse(line));
+
+    let cmds = commands(&mut bar);
+
+    // Past the rotated text, but not the stacked one
+    bar.window().click(10, 70, 1);
+    bar.handle_events().unwrap();
+    assert!(cmds.lock().unwrap().is_empty());
+
+    bar.set_vertical_text(VerticalText::Stacked);
+    bar.update(LemonParser::new().parse(line));
+    bar.window().click(10, 70, 1);
+    bar.handle_events().unwrap();
+    assert_eq!(*cmds.lock().unwrap(), vec!["a"]);
+}
+
+/// Get the text items of `line`.
+fn texts(line: &str) -> Vec<Text> {
+    LemonParser::new().parse(line).into_iter().filter_map(|item| {
+        match item {
+            FormatItem::Text(t, _) => Some(t),
+            _                      => None,
+        }
+    }).collect()
+}
+
+#[test]
+fn unchanged_text_is_not_shaped_again() {
+    let mut bar = bar("%{l}static%{r}12:00");
+    let before = texts("%{l}static%{r}12:00");
+    let layout = bar.cached_layout(&before[0]).unwrap();
+
+    update(&mut bar, "%{l}static%{r}12:01");
+    let after = texts("%{l}static%{r}12:01");
+    assert!(bar.cached_layout(&after[0]).unwrap() == layout);
+
+    // The clock was shaped anew, and its old text forgotten
+    assert!(bar.cached_layout(&after[1]).is_some());
+    assert!(bar.cached_layout(&before[1]).is_none());
+}
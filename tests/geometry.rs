@@ -0,0 +1,85 @@
+//! Tests of `Bar::set_geometry`, which should parse geometry strings like
+//! lemonbar's `-g` does.
+
+extern crate lemonade;
+
+use lemonade::{Bar, Error};
+use lemonade::window::Headless;
+
+const SCREEN: (u16, u16) = (400, 600);
+
+/// Set the geometry `g` on a new bar and get the size and position of
+/// its window.
+fn geometry(g: &str, bottom: bool) -> Result<((u16, u16), (i16, i16)), Error> {
+    let mut bar: Bar<Headless> = Bar::with_headless(SCREEN.0, SCREEN.1)?;
+    bar.bottom(bottom)?;
+    bar.set_geometry(g)?;
+
+    let w = bar.window();
+    Ok((w.get_size(), w.get_position()))
+}
+
+#[test]
+fn full() {
+    assert_eq!(geometry("100x20+10+5", false).unwrap(),
+               ((100, 20), (10, 5)));
+}
+
+#[test]
+fn leading_equals() {
+    assert_eq!(geometry("=100x20+10+5", false).unwrap(),
+               ((100, 20), (10, 5)));
+}
+
+#[test]
+fn width_only() {
+    assert_eq!(geometry("200", false).unwrap(), ((200, 25), (0, 0)));
+    assert_eq!(geometry("200x", false).unwrap(), ((200, 25), (0, 0)));
+}
+
+#[test]
+fn height_only() {
+    assert_eq!(geometry("x30", false).unwrap(), ((400, 30), (0, 0)));
+}
+
+#[test]
+fn position_only() {
+    assert_eq!(geometry("+10+20", false).unwrap(), ((400, 25), (10, 20)));
+    assert_eq!(geometry("+10", false).unwrap(), ((400, 25), (10, 0)));
+    assert_eq!(geometry("++20", false).unwrap(), ((400, 25), (0, 20)));
+}
+
+#[test]
+fn width_and_position() {
+    assert_eq!(geometry("100+5", false).unwrap(), ((100, 25), (5, 0)));
+    assert_eq!(geometry("100+5+6", false).unwrap(), ((100, 25), (5, 6)));
+}
+
+#[test]
+fn size_and_x() {
+    assert_eq!(geometry("100x20+5", false).unwrap(), ((100, 20), (5, 0)));
+}
+
+#[test]
+fn second_x_is_ignored() {
+    assert_eq!(geometry("100x20x5", false).unwrap(), ((100, 20), (0, 0)));
+}
+
+#[test]
+fn bottom() {
+    assert_eq!(geometry("100x20+10+5", true).unwrap(),
+               ((100, 20), (10, 600 - 20 - 5)));
+    assert_eq!(geometry("x20", true).unwrap(),
+               ((400, 20), (0, 600 - 20)));
+}
+
+#[test]
+fn invalid() {
+    for g in &["", "=", "abc", "-100", "100x-20", "100x20+1+2+3", "1 x2"] {
+        match geometry(g, false) {
+            Err(Error::Geometry(ref s)) => assert_eq!(s.as_str(), *g),
+            Err(e) => panic!("{:?}: unexpected error {}", g, e),
+            Ok(r)  => panic!("{:?}: accepted as {:?}", g, r),
+        }
+    }
+}
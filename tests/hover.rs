@@ -0,0 +1,55 @@
+//! Tests of hover styles, from `%{V}`.
+
+extern crate lemonade;
+
+#[path = "../src/bin/lemon.rs"]
+#[allow(dead_code)]
+mod lemon;
+mod common;
+
+use lemonade::window::Dock;
+use common::{bar, hover, leave, RED, GREEN, BLUE};
+
+#[test]
+fn styled_while_hovered() {
+    let mut bar = bar("%{B#f00}%{O50}%{V:#0f0:#00f}%{O50}%{V}%{O50}");
+    assert_eq!(bar.window().get_pixel(75, 5), RED);
+    assert_eq!(bar.window().get_pixel(75, 24), RED);
+
+    hover(&mut bar, 75, 10);
+    assert_eq!(bar.window().get_pixel(75, 5), GREEN);
+    assert_eq!(bar.window().get_pixel(75, 24), BLUE);
+    assert_eq!(bar.window().get_pixel(25, 5), RED);
+    assert_eq!(bar.window().get_pixel(125, 5), RED);
+
+    leave(&mut bar);
+    assert_eq!(bar.window().get_pixel(75, 5), RED);
+    assert_eq!(bar.window().get_pixel(75, 24), RED);
+}
+
+#[test]
+fn only_the_region_is_redrawn() {
+    let mut bar = bar("%{O50}%{V:#0f0}%{O50}%{V}%{O50}");
+    assert_eq!(bar.window().get_flushed(), Some(((0, 0), (400, 25))));
+
+    hover(&mut bar, 75, 10);
+    assert_eq!(bar.window().get_flushed(), Some(((50, 0), (51, 25))));
+
+    // Moving within the region changes nothing
+    bar.window_mut().flush();
+    hover(&mut bar, 80, 10);
+    assert_eq!(bar.window().get_flushed(), Some(((0, 0), (400, 25))));
+}
+
+#[test]
+fn neighbours_are_styled_apart() {
+    let mut bar = bar("%{B#f00 V:#0f0}%{O50}%{V}%{V:#0f0}%{O50}%{V}");
+
+    hover(&mut bar, 25, 10);
+    assert_eq!(bar.window().get_pixel(25, 5), GREEN);
+    assert_eq!(bar.window().get_pixel(75, 5), RED);
+
+    hover(&mut bar, 75, 10);
+    assert_eq!(bar.window().get_pixel(25, 5), RED);
+    assert_eq!(bar.window().get_pixel(75, 5), GREEN);
+}
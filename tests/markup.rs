@@ -0,0 +1,273 @@
+//! Conformance corpus for the lemonbar markup.
+//!
+//! Each entry is a line of input and the items `LemonParser` should turn
+//! it into, following lemonbar's behaviour on the same line. Items are
+//! written compactly, leaving out what is set to the parser defaults:
+//!
+//! - `"text"` followed by `F<fg>`, `B<bg>`, `U<underline>`, `u<overline>`,
+//!   `T"<font>"`, `A<button>:<command>` for each open area, `H:<tip>`
+//!   `M<button>:<label>=<command>,...`, `V<region>:<fg>:<bg>:<ul>` and
+//!   `C<cursor>`
+//! - `|` for the space between alignments
+//! - `O<width>` for an offset, with the same attributes as text
+//! - `Y` for the system tray, with the same attributes as text
+
+extern crate lemonade;
+
+#[path = "../src/bin/lemon.rs"]
+#[allow(dead_code)]
+mod lemon;
+
+use lemonade::format::{BG, Color, FormatItem};
+use lemon::LemonParser;
+
+const FONTS: &str = "A, B";
+
+fn hex(c: &Color) -> String {
+    let v = |f: f64| (f * 255.0).round() as u8;
+
+    if c.a == 1.0 {
+        format!("#{:02x}{:02x}{:02x}", v(c.r), v(c.g), v(c.b))
+    } else {
+        format!("#{:02x}{:02x}{:02x}{:02x}", v(c.a), v(c.r), v(c.g), v(c.b))
+    }
+}
+
+fn describe_bg(s: &mut String, bg: &BG) {
+    if hex(&bg.bg) != "#00000000" {
+        s.push_str(&format!(" B{}", hex(&bg.bg)));
+    }
+    if let Some(ref c) = bg.ul {
+        s.push_str(&format!(" U{}", hex(c)));
+    }
+    if let Some(ref c) = bg.ol {
+        s.push_str(&format!(" u{}", hex(c)));
+    }
+    for &(b, ref cmd) in &bg.cmd {
+        s.push_str(&format!(" A{}:{}", b, cmd));
+    }
+    if let Some(ref t) = bg.tip {
+        s.push_str(&format!(" H:{}", t));
+    }
+    if let Some(ref m) = bg.menu {
+        let items: Vec<String> = m.items.iter()
+            .map(|&(ref l, ref c)| format!("{}={}", l, c))
+            .collect();
+        s.push_str(&format!(" M{}:{}", m.button, items.join(",")));
+    }
+    if let Some(ref h) = bg.hover {
+        let colour = |c: &Option<Color>| {
+            c.as_ref().map_or(String::from("-"), hex)
+        };
+        s.push_str(&format!(" V{}:{}:{}:{}", h.region, colour(&h.fg),
+                            colour(&h.bg), colour(&h.ul)));
+    }
+    if let Some(ref c) = bg.cursor {
+        s.push_str(&format!(" C{}", c));
+    }
+}
+
+fn describe(item: &FormatItem) -> String {
+    let mut s;
+
+    match *item {
+        FormatItem::Text(ref t, ref bg) => {
+            s = format!("{:?}", t.text);
+            if hex(&t.fg) != "#ffffff" {
+                s.push_str(&format!(" F{}", hex(&t.fg)));
+            }
+            describe_bg(&mut s, bg);
+            if t.font != FONTS {
+                s.push_str(&format!(" T{:?}", t.font));
+            }
+        }
+
+        FormatItem::Offset(w, ref bg) => {
+            s = format!("O{}", w);
+            describe_bg(&mut s, bg);
+        }
+
+        FormatItem::Filler(ref bg) => {
+            s = String::from("|");
+            describe_bg(&mut s, bg);
+        }
+
+        FormatItem::Tray(ref bg) => {
+            s = String::from("Y");
+            describe_bg(&mut s, bg);
+        }
+
+        #[cfg(feature = "image")]
+        FormatItem::Image(ref i, ref bg) => {
+            s = format!("I{}", i.path);
+            describe_bg(&mut s, bg);
+        }
+    }
+
+    s
+}
+
+fn parser() -> LemonParser {
+    let mut lem = LemonParser::new();
+    lem.font_list = vec![String::from("A"), String::from("B")];
+    lem.ul = Color::from_hex("#0000ff").unwrap();
+    lem.ol = Color::from_hex("#00ff00").unwrap();
+    lem
+}
+
+fn describe_all(items: &[FormatItem]) -> Vec<String> {
+    items.iter().map(describe).collect()
+}
+
+const CORPUS: &[(&str, &[&str])] = &[
+    // Text and escapes
+    ("plain", &[r#""plain""#]),
+    ("", &[]),
+    ("%%", &[r#""%""#]),
+    ("100%% done", &[r#""100% done""#]),
+    ("a%b", &[r#""ab""#]),
+    ("a%", &[r#""a""#]),
+    ("%%{F#ff0000}a", &[r#""%{F#ff0000}a""#]),
+    ("a%{F#ff0000", &[r#""aF#ff0000""#]),
+    ("%{F#ff0000}", &[]),
+
+    // Colours
+    ("%{F#ff0000}a%{F-}b", &[r#""a" F#ff0000"#, r#""b""#]),
+    ("%{F#f00 B#00f}a", &[r#""a" F#ff0000 B#0000ff"#]),
+    ("%{B#80ff0000}a", &[r#""a" B#80ff0000"#]),
+    ("%{F#zzz}a%{Fff0000}b", &[r#""a""#, r#""b""#]),
+    ("%{R}a%{R}b", &[r#""a" F#00000000 B#ffffff"#, r#""b""#]),
+
+    // Attributes
+    ("%{+u}a%{-u}b", &[r#""a" U#0000ff"#, r#""b""#]),
+    ("%{U#ff0000 +u}a", &[r#""a" U#ff0000"#]),
+    ("%{!o}a%{!o}b", &[r#""a" u#00ff00"#, r#""b""#]),
+    ("%{+o +u}a%{u#ff0000}b", &[r#""a" U#0000ff u#00ff00"#,
+                                r#""b" U#0000ff u#ff0000"#]),
+
+    // Alignment
+    ("%{l}a%{c}b%{r}c", &[r#""a""#, "|", r#""b""#, "|", r#""c""#]),
+    ("%{r}a", &["|", "|", r#""a""#]),
+    ("%{c}a", &["|", r#""a""#, "|"]),
+    ("%{c}a%{c}b%{r}c%{l}d", &[r#""d""#, "|", r#""a""#, r#""b""#,
+                               "|", r#""c""#]),
+    ("%{B#ff0000 +u r}a", &["|", "|", r#""a" B#ff0000 U#0000ff"#]),
+
+    // Offsets
+    ("%{O10}a", &["O10", r#""a""#]),
+    ("%{B#ff0000 O5 +u}a%{O3}", &["O5 B#ff0000", r#""a" B#ff0000 U#0000ff"#,
+                                 "O3 B#ff0000"]),
+    ("%{A:x:}a%{O4}b%{A}", &[r#""a" A1:x"#, "O4 A1:x", r#""b" A1:x"#]),
+    ("%{Ox}a", &[r#""a""#]),
+
+    // System tray
+    ("%{r}a%{Y}", &["|", "|", r#""a""#, "Y"]),
+    ("%{B#ff0000 +u Y}", &["Y B#ff0000 U#0000ff"]),
+    ("%{A:x: Y A}", &["Y A1:x"]),
+
+    // Clickable areas
+    ("%{A:cmd:}a%{A}b", &[r#""a" A1:cmd"#, r#""b""#]),
+    ("%{A3:x\\:y:}a%{A3}b", &[r#""a" A3:x:y"#, r#""b""#]),
+    ("%{A:echo a b:}a", &[r#""a" A1:echo a b"#]),
+    ("%{A1:a: A3:b:}x%{A}y%{A}z", &[r#""x" A1:a A3:b"#, r#""y" A1:a"#,
+                                    r#""z""#]),
+    ("%{A2:a:}x%{A5}y", &[r#""x" A2:a"#, r#""y""#]),
+    ("%{A}a", &[r#""a""#]),
+    ("%{A:x}a", &[r#""a""#]),
+
+    // Tooltips
+    ("%{H:full title:}a%{H}b", &[r#""a" H:full title"#, r#""b""#]),
+    ("%{H:a\\:b: O2}x", &["O2 H:a:b", r#""x" H:a:b"#]),
+    ("%{H:outer: H:inner:}a%{H}b", &[r#""a" H:inner"#, r#""b" H:outer"#]),
+    ("%{H:x: A:y:}a", &[r#""a" A1:y H:x"#]),
+    ("%{H}a", &[r#""a""#]),
+    ("%{H:x}a", &[r#""a""#]),
+    ("%{r H:x:}a", &["|", "|", r#""a" H:x"#]),
+
+    // Menus
+    ("%{M:Lock:slock:Off:poweroff:}a%{M}b",
+     &[r#""a" M1:Lock=slock,Off=poweroff"#, r#""b""#]),
+    ("%{M3:a\\:b:c\\:d:}x", &[r#""x" M3:a:b=c:d"#]),
+    ("%{M:a:b: H:c:}x", &[r#""x" H:c M1:a=b"#]),
+    ("%{M:o:x: M:i:y:}a%{M}b", &[r#""a" M1:i=y"#, r#""b" M1:o=x"#]),
+    ("%{M:a:b:c}x", &[r#""x""#]),
+    ("%{M:}x", &[r#""x""#]),
+    ("%{M}x", &[r#""x""#]),
+
+    // Hover styles
+    ("%{V#fff:#333}a%{V}b", &[r#""a" V0:#ffffff:#333333:-"#, r#""b""#]),
+    ("%{V::#f00}a", &[r#""a" V0:-:-:#ff0000"#]),
+    ("%{V#fff}a%{V}%{V#fff}b",
+     &[r#""a" V0:#ffffff:-:-"#, r#""b" V1:#ffffff:-:-"#]),
+    ("%{V#fff V#000}a%{V}b",
+     &[r#""a" V1:#000000:-:-"#, r#""b" V0:#ffffff:-:-"#]),
+    ("%{V-:#000 O3}", &["O3 V0:-:#000000:-"]),
+    ("%{Vx}a", &[r#""a" V0:-:-:-"#]),
+    ("%{V}a", &[r#""a""#]),
+
+    // Cursors
+    ("%{Cpointer}a%{C}b", &[r#""a" Cpointer"#, r#""b""#]),
+    ("%{Cpointer Cew-resize}a%{C}b",
+     &[r#""a" Cew-resize"#, r#""b" Cpointer"#]),
+    ("%{A:x: Cxterm}a", &[r#""a" A1:x Cxterm"#]),
+    ("%{C}a", &[r#""a""#]),
+
+    // Fonts
+    ("%{T2}a%{T-}b", &[r#""a" T"B""#, r#""b""#]),
+    ("%{T1}a%{T3}b", &[r#""a" T"A""#, r#""b""#]),
+    ("%{T2}a%{T0}b", &[r#""a" T"B""#, r#""b""#]),
+
+    // Unknown commands end their block
+    ("%{F#ff0000 Z B#0000ff}a", &[r#""a" F#ff0000"#]),
+    ("%{?}a", &[r#""a""#]),
+
+    // Screens, with a single one
+    ("a%{S+}b%{S1}c", &[r#""a""#, r#""b""#, r#""c""#]),
+    ("%{r}a%{S0}b", &[r#""b""#, "|", "|", r#""a""#]),
+];
+
+#[test]
+fn corpus() {
+    for &(line, expected) in CORPUS {
+        let items = parser().parse(line);
+        assert_eq!(describe_all(&items), expected, "Parsing {:?}", line);
+    }
+}
+
+/// Parse `line` for `n` screens.
+fn screens(line: &str, n: usize) -> Vec<Vec<String>> {
+    parser().parse_screens(line, n).iter()
+            .map(|s| describe_all(s))
+            .collect()
+}
+
+#[test]
+fn screens_are_selected() {
+    assert_eq!(screens("a%{S+}b%{S-}%{r}c", 2),
+               vec![vec![r#""a""#, "|", "|", r#""c""#],
+                    vec![r#""b""#]]);
+
+    assert_eq!(screens("%{Sl}a%{Sf}b%{S1}c", 3),
+               vec![vec![r#""b""#], vec![r#""c""#], vec![r#""a""#]]);
+}
+
+#[test]
+fn screens_are_clamped() {
+    assert_eq!(screens("%{S9}a%{S+}b", 2),
+               vec![Vec::<&str>::new(), vec![r#""a""#, r#""b""#]]);
+
+    assert_eq!(screens("a%{S-}b", 2),
+               vec![vec![r#""a""#, r#""b""#], Vec::new()]);
+}
+
+#[test]
+fn attributes_carry_over_screens() {
+    assert_eq!(screens("%{F#ff0000 A:x:}a%{S+}b", 2),
+               vec![vec![r#""a" F#ff0000 A1:x"#],
+                    vec![r#""b" F#ff0000 A1:x"#]]);
+}
+
+#[test]
+fn lines_without_screens_are_on_every_screen() {
+    assert_eq!(screens("%{c}a", 3), vec![vec!["|", r#""a""#, "|"]; 3]);
+}
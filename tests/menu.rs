@@ -0,0 +1,158 @@
+//! Tests of menus, opened by clicking regions marked with `%{M}`.
+
+extern crate lemonade;
+
+#[path = "../src/bin/lemon.rs"]
+#[allow(dead_code)]
+mod lemon;
+mod common;
+
+use std::sync::{Arc, Mutex};
+
+use lemonade::Bar;
+use lemonade::format::FormatItem;
+use lemonade::window::{Headless, Key, Popup, PopupEvent};
+use lemon::LemonParser;
+
+const LINE: &str = concat!("%{O100}",
+                           "%{M:Lock:slock:Suspend:zzz:Off:poweroff:}",
+                           "%{O50}%{M}");
+
+/// Create a bar showing `LINE`, and get the commands it runs.
+fn bar() -> (Bar<Headless>, Arc<Mutex<Vec<String>>>) {
+    let mut bar = common::bar(LINE);
+    let cmds = common::commands(&mut bar);
+    (bar, cmds)
+}
+
+/// Open the menu, and get the height of its entries.
+fn open(bar: &mut Bar<Headless>) -> i16 {
+    bar.window().click(120, 10, 1);
+    bar.handle_events().unwrap();
+
+    let (_, (_, h)) = bar.window().get_popup().unwrap();
+    h as i16 / 3
+}
+
+fn send(bar: &mut Bar<Headless>, events: &[PopupEvent]) {
+    for &e in events {
+        bar.window_mut().send_popup_event(e);
+    }
+    bar.handle_events().unwrap();
+}
+
+#[test]
+fn opened_under_the_region() {
+    let (mut bar, cmds) = bar();
+    open(&mut bar);
+
+    let ((x, y), (w, _)) = bar.window().get_popup().unwrap();
+    assert_eq!(bar.window().get_popup_kind(), Some(Popup::Menu));
+    assert_eq!((x, y), (100, 25));
+    assert!(w >= 50);
+    assert!(cmds.lock().unwrap().is_empty());
+}
+
+#[test]
+fn only_opened_by_its_button() {
+    let (mut bar, _) = bar();
+    bar.window().click(120, 10, 3);
+    bar.handle_events().unwrap();
+    assert_eq!(bar.window().get_popup(), None);
+
+    bar.window().click(50, 10, 1);
+    bar.handle_events().unwrap();
+    assert_eq!(bar.window().get_popup(), None);
+}
+
+#[test]
+fn chosen_with_the_keyboard() {
+    let (mut bar, cmds) = bar();
+    open(&mut bar);
+
+    // Enter does nothing until an entry is selected
+    send(&mut bar, &[PopupEvent::Key(Key::Enter)]);
+    assert!(bar.window().get_popup().is_some());
+
+    send(&mut bar, &[
+        PopupEvent::Key(Key::Down),
+        PopupEvent::Key(Key::Down),
+        PopupEvent::Key(Key::Enter),
+    ]);
+    assert_eq!(*cmds.lock().unwrap(), vec!["zzz"]);
+    assert_eq!(bar.window().get_popup(), None);
+}
+
+#[test]
+fn selection_wraps_around() {
+    let (mut bar, cmds) = bar();
+    open(&mut bar);
+
+    send(&mut bar, &[
+        PopupEvent::Key(Key::Up),
+        PopupEvent::Key(Key::Enter),
+    ]);
+    assert_eq!(*cmds.lock().unwrap(), vec!["poweroff"]);
+
+    open(&mut bar);
+    send(&mut bar, &[
+        PopupEvent::Press { x: 0, y: 0, button: 5 },
+        PopupEvent::Press { x: 0, y: 0, button: 4 },
+        PopupEvent::Key(Key::Enter),
+    ]);
+    assert_eq!(*cmds.lock().unwrap(), vec!["poweroff", "poweroff"]);
+}
+
+#[test]
+fn chosen_with_the_pointer() {
+    let (mut bar, cmds) = bar();
+    let row = open(&mut bar);
+
+    send(&mut bar, &[PopupEvent::Press { x: 5, y: row + 2, button: 1 }]);
+    assert_eq!(*cmds.lock().unwrap(), vec!["zzz"]);
+
+    // Hovering selects
+    let row = open(&mut bar);
+    send(&mut bar, &[
+        PopupEvent::Motion { x: 5, y: 2 * row + 2 },
+        PopupEvent::Key(Key::Enter),
+    ]);
+    assert_eq!(*cmds.lock().unwrap(), vec!["zzz", "poweroff"]);
+}
+
+#[test]
+fn closed_without_choosing() {
+    let (mut bar, cmds) = bar();
+    open(&mut bar);
+    send(&mut bar, &[PopupEvent::Key(Key::Escape)]);
+    assert_eq!(bar.window().get_popup(), None);
+
+    // Clicking elsewhere
+    open(&mut bar);
+    send(&mut bar, &[PopupEvent::Press { x: -5, y: -5, button: 1 }]);
+    assert_eq!(bar.window().get_popup(), None);
+
+    assert!(cmds.lock().unwrap().is_empty());
+}
+
+#[test]
+fn not_opened_when_empty() {
+    // Only the library lets menus have no entries, not the parser
+    let mut fmt = LemonParser::new().parse(LINE);
+    for item in &mut fmt {
+        if let FormatItem::Offset(_, ref mut bg) = *item {
+            if let Some(ref mut m) = bg.menu {
+                m.items.clear();
+            }
+        }
+    }
+
+    let mut bar = common::headless();
+    bar.set_fmt(fmt);
+    bar.draw();
+
+    bar.window().click(120, 10, 1);
+    bar.handle_events().unwrap();
+    send(&mut bar, &[PopupEvent::Key(Key::Up), PopupEvent::Key(Key::Down)]);
+    assert_eq!(bar.window().get_popup(), None);
+}
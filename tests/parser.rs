@@ -0,0 +1,86 @@
+//! Tests of the options of `LemonParser` set from the command line.
+
+extern crate lemonade;
+
+#[path = "../src/bin/lemon.rs"]
+#[allow(dead_code)]
+mod lemon;
+
+use lemonade::format::{Color, FormatItem};
+use lemon::LemonParser;
+
+/// Get the non-empty texts of `items` along with their vertical offset.
+fn offsets(items: &[FormatItem]) -> Vec<(String, f64)> {
+    items.iter().filter_map(|i| match *i {
+        FormatItem::Text(ref t, _) if !t.text.is_empty() => {
+            Some((t.text.clone(), t.offset))
+        }
+        _ => None,
+    }).collect()
+}
+
+fn fonts() -> Vec<String> {
+    vec!["Sans 10", "Serif 12", "Mono 9"].into_iter()
+                                         .map(String::from)
+                                         .collect()
+}
+
+#[test]
+fn no_offset() {
+    let mut lem = LemonParser::new();
+    lem.font_list = fonts();
+
+    assert_eq!(offsets(&lem.parse("a%{T2}b")),
+               vec![("a".to_string(), 0.0), ("b".to_string(), 0.0)]);
+}
+
+#[test]
+fn font_offsets() {
+    let mut lem = LemonParser::new();
+    lem.font_list = fonts();
+    lem.font_offsets = vec![2.0, -1.0];
+
+    // The third font has no offset and uses the first one
+    assert_eq!(offsets(&lem.parse("a%{T1}b%{T2}c%{T3}d%{T-}e")),
+               vec![("a".to_string(), 2.0),
+                    ("b".to_string(), 2.0),
+                    ("c".to_string(), -1.0),
+                    ("d".to_string(), 2.0),
+                    ("e".to_string(), 2.0)]);
+}
+
+#[test]
+fn line_sizes() {
+    let mut lem = LemonParser::new();
+    lem.ul_size = 3.0;
+    lem.ol_size = 2.0;
+
+    for item in lem.parse("%{+u}%{+o}a%{l}b") {
+        let bg = match item {
+            FormatItem::Text(_, bg)
+            | FormatItem::Filler(bg)
+            | FormatItem::Offset(_, bg)
+            | FormatItem::Tray(bg) => bg,
+            #[cfg(feature = "image")]
+            FormatItem::Image(_, bg) => bg,
+        };
+
+        assert_eq!(bg.ul_size, 3.0);
+        assert_eq!(bg.ol_size, 2.0);
+    }
+}
+
+#[test]
+fn default_colours() {
+    let mut lem = LemonParser::new();
+    lem.fg = Color::from_hex("#ff0000").unwrap();
+    lem.bg = Color::from_hex("#00ff00").unwrap();
+
+    match lem.parse("a")[0] {
+        FormatItem::Text(ref t, ref bg) => {
+            assert_eq!((t.fg.r, t.fg.g, t.fg.b), (1.0, 0.0, 0.0));
+            assert_eq!((bg.bg.r, bg.bg.g, bg.bg.b), (0.0, 1.0, 0.0));
+        }
+        _ => panic!("Expected a text"),
+    }
+}
@@ -0,0 +1,230 @@
+//! Golden image tests for the renderer.
+//!
+//! Each test parses a line of lemonbar markup, draws it on a headless bar
+//! and compares the result with `tests/golden/<name>.png`. On failure, the
+//! rendered image and an image highlighting the differing pixels are
+//! written to `target/golden/`.
+//!
+//! Set `LEMONADE_BLESS=1` to (re)generate the reference images. Without
+//! it, a missing or unreadable reference fails the test.
+
+extern crate lemonade;
+extern crate png;
+
+#[path = "../src/bin/lemon.rs"]
+#[allow(dead_code)]
+mod lemon;
+
+use std::env;
+use std::fs::{self, File};
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+use png::HasParameters;
+
+use lemonade::Bar;
+use lemonade::window::{Dock, Headless};
+use lemon::LemonParser;
+
+/// Maximum difference allowed per colour channel, to leave room for
+/// small differences in font rasterization.
+const TOLERANCE: u8 = 8;
+
+const WIDTH:  u16 = 400;
+const HEIGHT: u16 = 20;
+
+/// Fonts used by the tests, chosen to be commonly available.
+const FONTS: [&str; 2] = ["DejaVu Sans 10", "DejaVu Serif Bold 12"];
+
+/// An RGBA image with 8 bits per channel, without premultiplied alpha.
+struct Image {
+    w: u32,
+    h: u32,
+    data: Vec<u8>,
+}
+
+impl Image {
+
+    /// Convert the premultiplied ARGB32 pixels drawn by cairo.
+    fn from_argb32(w: u32, h: u32, pixels: &[u32]) -> Image {
+        let mut data = Vec::with_capacity(pixels.len() * 4);
+
+        for &p in pixels {
+            let a = (p >> 24) as u8;
+            let unmul = |c: u32| -> u8 {
+                let c = (c & 0xff) as u8;
+                if a == 0 { 0 }
+                else { (c as u32 * 255 / a as u32) as u8 }
+            };
+
+            data.push(unmul(p >> 16));
+            data.push(unmul(p >> 8));
+            data.push(unmul(p));
+            data.push(a);
+        }
+
+        Image { w, h, data }
+    }
+
+    fn load(path: &Path) -> Option<Image> {
+        let file = File::open(path).ok()?;
+        let decoder = png::Decoder::new(file);
+        let (info, mut reader) = decoder.read_info().ok()?;
+
+        if info.color_type != png::ColorType::RGBA
+            || info.bit_depth != png::BitDepth::Eight {
+            panic!("{} must be an 8-bit RGBA png", path.display());
+        }
+
+        let mut data = vec![0; info.buffer_size()];
+        reader.next_frame(&mut data).ok()?;
+
+        Some(Image { w: info.width, h: info.height, data })
+    }
+
+    fn save(&self, path: &Path) {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).unwrap();
+        }
+
+        let file = BufWriter::new(File::create(path).unwrap());
+        let mut enc = png::Encoder::new(file, self.w, self.h);
+        enc.set(png::ColorType::RGBA).set(png::BitDepth::Eight);
+
+        let mut writer = enc.write_header().unwrap();
+        writer.write_image_data(&self.data).unwrap();
+    }
+
+    /// Compare with `other`, returning the number of differing pixels
+    /// and an image showing them in red over a faded copy of `self`.
+    fn diff(&self, other: &Image) -> (usize, Image) {
+        let mut count = 0;
+        let mut data = Vec::with_capacity(self.data.len());
+
+        for (a, b) in self.data.chunks(4).zip(other.data.chunks(4)) {
+            let differs = a.iter().zip(b)
+                .any(|(&x, &y)| (x as i32 - y as i32).abs() > TOLERANCE as i32);
+
+            if differs {
+                count += 1;
+                data.extend_from_slice(&[255, 0, 0, 255]);
+            } else {
+                let l = ((a[0] as u32 + a[1] as u32 + a[2] as u32) / 3) as u8;
+                data.extend_from_slice(&[l / 4, l / 4, l / 4, 255]);
+            }
+        }
+
+        (count, Image { w: self.w, h: self.h, data })
+    }
+}
+
+fn golden_path(name: &str) -> PathBuf {
+    Path::new("tests/golden").join(format!("{}.png", name))
+}
+
+fn output_path(name: &str, kind: &str) -> PathBuf {
+    Path::new("target/golden").join(format!("{}.{}.png", name, kind))
+}
+
+/// Draw `markup` and compare it with the reference image `name`.
+fn check(name: &str, markup: &str) {
+    let mut lem = LemonParser::new();
+    lem.font_list = FONTS.iter().map(|s| s.to_string()).collect();
+    lem.bg = lemonade::format::Color::from_hex("#222222").unwrap();
+    lem.fg = lemonade::format::Color::from_hex("#dddddd").unwrap();
+
+    let mut bar: Bar<Headless> = Bar::with_headless(WIDTH, 600).unwrap();
+    bar.set_geometry(&format!("{}x{}", WIDTH, HEIGHT)).unwrap();
+    bar.set_fmt(lem.parse(markup));
+    bar.draw();
+
+    let actual = Image::from_argb32(WIDTH as u32, HEIGHT as u32,
+                                    &bar.window().get_pixels());
+
+    let golden = golden_path(name);
+    let bless = env::var_os("LEMONADE_BLESS").is_some();
+
+    if bless {
+        actual.save(&golden);
+        eprintln!("Wrote reference image {}", golden.display());
+        return;
+    }
+
+    let expected = match Image::load(&golden) {
+        Some(img) => img,
+        None => {
+            actual.save(&output_path(name, "actual"));
+            panic!("{}: cannot read {}, set LEMONADE_BLESS=1 to create it",
+                   name, golden.display());
+        }
+    };
+
+    if (expected.w, expected.h) != (actual.w, actual.h) {
+        actual.save(&output_path(name, "actual"));
+        panic!("{}: size is {}x{}, expected {}x{}", name,
+               actual.w, actual.h, expected.w, expected.h);
+    }
+
+    let (count, diff) = actual.diff(&expected);
+    if count > 0 {
+        actual.save(&output_path(name, "actual"));
+        diff.save(&output_path(name, "diff"));
+
+        panic!("{}: {} pixels differ from {}, see {}", name, count,
+               golden.display(), output_path(name, "diff").display());
+    }
+}
+
+#[test]
+fn plain_text() {
+    check("plain_text", "lemonade");
+}
+
+#[test]
+fn alignment() {
+    check("alignment", "%{l}left%{c}center%{r}right");
+}
+
+#[test]
+fn alignment_repeated() {
+    check("alignment_repeated", "%{c}one%{c}two%{r}three%{l}four");
+}
+
+#[test]
+fn colours() {
+    check("colours", "%{B#ff0000}red%{B-} %{F#00ff00}green%{F-} \
+                      %{B#800000ff}translucent");
+}
+
+#[test]
+fn swap() {
+    check("swap", "%{B#0000ff}%{F#ffff00}normal%{R}swapped%{R}normal");
+}
+
+#[test]
+fn underline() {
+    check("underline", "%{U#ff0000}%{+u}underlined%{-u} plain");
+}
+
+#[test]
+fn overline() {
+    check("overline", "%{u#00ff00}%{+o}overlined%{-o} %{!o}toggled");
+}
+
+#[test]
+fn fonts() {
+    check("fonts", "%{T1}first %{T2}second %{T-}default");
+}
+
+#[cfg(feature = "image")]
+#[test]
+fn image() {
+    check("image", "%{l}before%{I16:assets/lemonade.png}after\
+                    %{r}%{B#ff0000}%{I12:assets/lemonade.png}");
+}
+
+#[test]
+fn no_pixels_without_width() {
+    let mut window = Headless::new(400, 600);
+    window.set_size(0, 20).unwrap();
+    assert!(window.get_pixels().is_empty());
+}
@@ -0,0 +1,81 @@
+//! Tests of HiDPI scaling, from the DPI of outputs and `Xft.dpi`.
+
+extern crate lemonade;
+
+use lemonade::Bar;
+use lemonade::window::{xft_dpi, Headless, Output};
+
+fn output(name: &str, w: u16, h: u16, mm_w: u32, mm_h: u32) -> Output {
+    Output { name: String::from(name), x: 0, y: 0, w, h, mm_w, mm_h,
+             primary: false }
+}
+
+#[test]
+fn output_scale() {
+    // A 24" 1080p monitor, and a 27" 4K one
+    assert_eq!(output("a", 1920, 1080, 531, 299).scale(), 1.0);
+    assert_eq!(output("b", 3840, 2160, 597, 336).scale(), 1.75);
+
+    // Rotated outputs keep their scale
+    assert_eq!(output("c", 2160, 3840, 597, 336).scale(), 1.75);
+}
+
+#[test]
+fn unknown_or_low_dpi() {
+    assert_eq!(output("a", 1920, 1080, 0, 0).scale(), 1.0);
+    assert_eq!(output("b", 1024, 768, 1600, 900).scale(), 1.0);
+}
+
+#[test]
+fn xft_dpi_resource() {
+    assert_eq!(xft_dpi("Xft.dpi:\t192\n"), Some(192.0));
+    assert_eq!(xft_dpi("Xft.antialias:\t1\nXft.dpi: 144\nXft.hinting:\t1"),
+               Some(144.0));
+    assert_eq!(xft_dpi("Xft.antialias:\t1\n"), None);
+    assert_eq!(xft_dpi("Xft.dpi:\tlots\n"), None);
+    assert_eq!(xft_dpi(""), None);
+}
+
+#[test]
+fn geometry_is_scaled() {
+    let mut bar: Bar<Headless> = Bar::with_headless(3840, 2160).unwrap();
+    bar.set_scale(2.0).unwrap();
+    assert_eq!(bar.window().get_size(), (3840, 50));
+
+    bar.set_geometry("100x20+10+5").unwrap();
+    assert_eq!(bar.window().get_size(), (200, 40));
+    assert_eq!(bar.window().get_position(), (20, 10));
+
+    // The geometry is applied again when the scale changes
+    bar.set_scale(1.5).unwrap();
+    assert_eq!(bar.window().get_size(), (150, 30));
+}
+
+#[test]
+fn scale_follows_the_output() {
+    let mut bar: Bar<Headless> = Bar::with_headless(1920, 1080).unwrap();
+    bar.window_mut().set_outputs(vec![
+        output("low",  1920, 1080, 531, 299),
+        output("high", 3840, 2160, 597, 336),
+    ]);
+
+    bar.set_output("high").unwrap();
+    assert_eq!(bar.scale(), 1.75);
+    assert_eq!(bar.window().get_size(), (3840, 44));
+
+    bar.set_output("low").unwrap();
+    assert_eq!(bar.scale(), 1.0);
+    assert_eq!(bar.window().get_size(), (1920, 25));
+
+    // Unless it was set
+    bar.set_scale(2.0).unwrap();
+    bar.set_output("high").unwrap();
+    assert_eq!(bar.scale(), 2.0);
+}
+
+#[test]
+fn invalid_scale() {
+    let mut bar: Bar<Headless> = Bar::with_headless(1920, 1080).unwrap();
+    assert!(bar.set_scale(0.0).is_err());
+    assert!(bar.set_scale(-1.0).is_err());
+}
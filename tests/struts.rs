@@ -0,0 +1,115 @@
+//! Tests of the struts published for the space taken by the bar.
+
+extern crate lemonade;
+
+use lemonade::Bar;
+use lemonade::window::{Edge, Headless, Output, Struts};
+
+fn output(name: &str, x: i16, y: i16, w: u16, h: u16) -> Output {
+    Output {
+        name: String::from(name), x, y, w, h,
+        mm_w: 0, mm_h: 0, primary: false,
+    }
+}
+
+#[test]
+fn top() {
+    let s = Struts::new((0, 0), (1920, 20), (1920, 1080), Edge::Top);
+
+    assert_eq!(s.partial, [0, 0, 20, 0, 0, 0, 0, 0, 0, 1919, 0, 0]);
+    assert_eq!(s.legacy(), [0, 0, 20, 0]);
+}
+
+#[test]
+fn bottom() {
+    let s = Struts::new((0, 1060), (1920, 20), (1920, 1080), Edge::Bottom);
+
+    assert_eq!(s.partial, [0, 0, 0, 20, 0, 0, 0, 0, 0, 0, 0, 1919]);
+    assert_eq!(s.legacy(), [0, 0, 0, 20]);
+}
+
+#[test]
+fn left() {
+    let s = Struts::new((0, 0), (30, 1080), (1920, 1080), Edge::Left);
+
+    assert_eq!(s.partial, [30, 0, 0, 0, 0, 1079, 0, 0, 0, 0, 0, 0]);
+    assert_eq!(s.legacy(), [30, 0, 0, 0]);
+}
+
+#[test]
+fn right() {
+    let s = Struts::new((1890, 100), (30, 800), (1920, 1080), Edge::Right);
+
+    assert_eq!(s.partial, [0, 30, 0, 0, 0, 0, 100, 899, 0, 0, 0, 0]);
+    assert_eq!(s.legacy(), [0, 30, 0, 0]);
+}
+
+#[test]
+fn offsets() {
+    // The gap between the edge of the screen and the bar is reserved too
+    let s = Struts::new((100, 10), (800, 20), (1920, 1080), Edge::Top);
+    assert_eq!(s.partial, [0, 0, 30, 0, 0, 0, 0, 0, 100, 899, 0, 0]);
+
+    let s = Struts::new((100, 1050), (800, 20), (1920, 1080), Edge::Bottom);
+    assert_eq!(s.partial, [0, 0, 0, 30, 0, 0, 0, 0, 0, 0, 100, 899]);
+}
+
+#[test]
+fn second_output() {
+    // A bar at the top of an output right of a 1920x1080 one
+    let s = Struts::new((1920, 0), (2560, 25), (4480, 1440), Edge::Top);
+    assert_eq!(s.partial, [0, 0, 25, 0, 0, 0, 0, 0, 1920, 4479, 0, 0]);
+
+    // Struts are relative to the root window, so a bar at the bottom of
+    // the shorter output reserves what is below it on the taller one
+    let s = Struts::new((0, 1055), (1920, 25), (4480, 1440), Edge::Bottom);
+    assert_eq!(s.partial, [0, 0, 0, 385, 0, 0, 0, 0, 0, 0, 0, 1919]);
+}
+
+#[test]
+fn outside_of_the_screen() {
+    // Only the part of the bar on the screen is reserved
+    let s = Struts::new((-100, 0), (400, 20), (200, 100), Edge::Top);
+    assert_eq!(s.partial, [0, 0, 20, 0, 0, 0, 0, 0, 0, 199, 0, 0]);
+
+    let s = Struts::new((300, 0), (400, 20), (200, 100), Edge::Top);
+    assert_eq!(s.partial, [0; 12]);
+}
+
+#[test]
+fn bar_on_outputs() {
+    let mut bar: Bar<Headless> = Bar::with_headless(1920, 1080).unwrap();
+    bar.window_mut().set_outputs(vec![
+        output("left",  0,    0, 1920, 1080),
+        output("right", 1920, 0, 2560, 1440),
+    ]);
+
+    bar.set_output("right").unwrap();
+    bar.set_geometry("x30+10+5").unwrap();
+    assert_eq!(bar.window().get_struts().partial,
+               [0, 0, 35, 0, 0, 0, 0, 0, 1930, 4479, 0, 0]);
+
+    bar.bottom(true).unwrap();
+    assert_eq!(bar.window().get_struts().partial,
+               [0, 0, 0, 35, 0, 0, 0, 0, 0, 0, 1930, 4479]);
+}
+
+#[test]
+fn vertical_bar_on_outputs() {
+    let mut bar: Bar<Headless> = Bar::with_headless(1920, 1080).unwrap();
+    bar.window_mut().set_outputs(vec![
+        output("left",  0,    0, 1920, 1080),
+        output("right", 1920, 0, 2560, 1440),
+    ]);
+
+    // The bar turns with the same thickness, and spans the whole height
+    bar.set_output("right").unwrap();
+    bar.set_edge(Edge::Right).unwrap();
+    assert_eq!(bar.window().get_struts().partial,
+               [0, 25, 0, 0, 0, 0, 0, 1439, 0, 0, 0, 0]);
+
+    bar.set_geometry("30x400+0+100").unwrap();
+    bar.set_edge(Edge::Left).unwrap();
+    assert_eq!(bar.window().get_struts().partial,
+               [1950, 0, 0, 0, 100, 499, 0, 0, 0, 0, 0, 0]);
+}
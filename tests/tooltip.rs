@@ -0,0 +1,107 @@
+//! Tests of tooltips, shown from `%{H}` when hovering their region.
+
+extern crate lemonade;
+
+#[path = "../src/bin/lemon.rs"]
+#[allow(dead_code)]
+mod lemon;
+mod common;
+
+use std::time::Duration;
+
+use lemonade::Bar;
+use lemonade::window::{Edge, Headless};
+use common::{hover, leave};
+
+/// Create a bar showing `line`, with tooltips shown right away.
+fn bar(line: &str) -> Bar<Headless> {
+    let mut bar = common::headless();
+    bar.set_tooltip_delay(Duration::from_millis(0));
+    common::show(&mut bar, line);
+    bar
+}
+
+#[test]
+fn shown_below_the_bar() {
+    let mut bar = bar("%{O100}%{H:tip:}%{O50}%{H}");
+    hover(&mut bar, 120, 10);
+
+    let ((x, y), (w, h)) = bar.window().get_popup().unwrap();
+    assert_eq!(y, 25);
+    assert!(w > 0 && h > 0);
+
+    // Centred on the region
+    assert_eq!(x + w as i16 / 2, 125);
+}
+
+#[test]
+fn shown_above_bottom_bars() {
+    let mut bar = bar("%{H:tip:}%{O50}%{H}");
+    bar.set_edge(Edge::Bottom).unwrap();
+    bar.draw();
+    hover(&mut bar, 10, 10);
+
+    let ((_, y), (_, h)) = bar.window().get_popup().unwrap();
+    assert_eq!(y + h as i16, 575);
+}
+
+#[test]
+fn kept_on_the_output() {
+    let mut bar = bar("%{r}%{H:a rather long tooltip:}%{O4}%{H}");
+    hover(&mut bar, 398, 10);
+
+    let ((x, _), (w, _)) = bar.window().get_popup().unwrap();
+    assert_eq!(x + w as i16, 400);
+}
+
+#[test]
+fn regions_without_tooltips() {
+    let mut bar = bar("%{O100}%{H:tip:}%{O50}%{H}");
+    hover(&mut bar, 50, 10);
+    assert_eq!(bar.window().get_popup(), None);
+    assert_eq!(bar.tooltip_timeout(), None);
+
+    // Moving away hides the tooltip
+    hover(&mut bar, 120, 10);
+    assert!(bar.window().get_popup().is_some());
+    hover(&mut bar, 50, 10);
+    assert_eq!(bar.window().get_popup(), None);
+}
+
+#[test]
+fn hidden_on_leave() {
+    let mut bar = bar("%{H:tip:}%{O50}%{H}");
+    hover(&mut bar, 10, 10);
+    assert!(bar.window().get_popup().is_some());
+
+    leave(&mut bar);
+    assert_eq!(bar.window().get_popup(), None);
+}
+
+#[test]
+fn shown_after_the_delay() {
+    let mut bar = bar("%{H:tip:}%{O50}%{H}");
+    bar.set_tooltip_delay(Duration::from_secs(60));
+    hover(&mut bar, 10, 10);
+
+    assert_eq!(bar.window().get_popup(), None);
+    assert!(bar.tooltip_timeout().unwrap() > Duration::from_secs(50));
+
+    // Moving within the region keeps it pending, until it is due
+    bar.set_tooltip_delay(Duration::from_millis(0));
+    hover(&mut bar, 20, 10);
+    assert!(bar.window().get_popup().is_some());
+    assert_eq!(bar.tooltip_timeout(), None);
+}
+
+#[test]
+fn neighbours_share_a_tooltip() {
+    let mut bar = bar("%{H:tip:}%{O50}%{O50}%{H}");
+    hover(&mut bar, 10, 10);
+    let first = bar.window().get_popup().unwrap();
+
+    // Both offsets are one region, so the tooltip stays where it is
+    hover(&mut bar, 90, 10);
+    assert_eq!(bar.window().get_popup(), Some(first));
+    assert_eq!((first.0).0 + (first.1).0 as i16 / 2, 50);
+}
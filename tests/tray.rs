@@ -0,0 +1,69 @@
+//! Tests of the layout of system tray icons, where `%{Y}` is.
+
+extern crate lemonade;
+
+#[path = "../src/bin/lemon.rs"]
+#[allow(dead_code)]
+mod lemon;
+mod common;
+
+use lemonade::Bar;
+use lemonade::window::{Edge, Headless};
+
+/// Create a bar with `icons` tray icons, showing `line`.
+fn bar(line: &str, icons: usize) -> Bar<Headless> {
+    let mut bar = common::headless();
+    bar.window_mut().set_tray_icons(icons);
+    common::show(&mut bar, line);
+    bar
+}
+
+#[test]
+fn icons_are_placed_at_the_tray() {
+    let bar = bar("%{O10}%{Y}%{O5}", 2);
+    assert_eq!(bar.window().get_tray_icons(),
+               (vec![(10, 0), (35, 0)], 25));
+}
+
+#[test]
+fn aligned_tray() {
+    let right = bar("%{r}%{Y}", 2);
+    assert_eq!(right.window().get_tray_icons(),
+               (vec![(350, 0), (375, 0)], 25));
+
+    let centre = bar("%{c}%{Y}", 2);
+    assert_eq!(centre.window().get_tray_icons(),
+               (vec![(175, 0), (200, 0)], 25));
+}
+
+#[test]
+fn only_the_first_tray_has_icons() {
+    let bar = bar("%{Y}%{O10}%{Y}", 1);
+    assert_eq!(bar.window().get_tray_icons(), (vec![(0, 0)], 25));
+}
+
+#[test]
+fn icons_are_hidden_without_tray() {
+    let bar = bar("%{O10}", 2);
+    assert_eq!(bar.window().get_tray_icons(), (Vec::new(), 0));
+}
+
+#[test]
+fn vertical_tray() {
+    let mut bar = bar("%{O10}%{Y}", 2);
+    bar.set_edge(Edge::Left).unwrap();
+    bar.draw();
+
+    assert_eq!(bar.window().get_tray_icons(),
+               (vec![(0, 10), (0, 35)], 25));
+}
+
+#[test]
+fn icons_coming_and_going() {
+    let mut bar = bar("%{r}%{Y}", 1);
+
+    bar.window_mut().set_tray_icons(3);
+    bar.handle_events().unwrap();
+    assert_eq!(bar.window().get_tray_icons(),
+               (vec![(325, 0), (350, 0), (375, 0)], 25));
+}
@@ -0,0 +1,354 @@
+//! A compositor speaking just enough of the wayland protocol for the bar.
+//!
+//! It runs on a thread at the other end of a socket pair, records what the
+//! client asks of its layer surfaces, and sends pointer events and buffer
+//! releases when told to.
+
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use std::os::unix::io::{IntoRawFd, RawFd};
+use std::os::unix::net::UnixStream;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
+use std::time::{Duration, Instant};
+
+pub const OUTPUT: &str = "TEST-1";
+pub const OUTPUT_SIZE: (i32, i32) = (400, 600);
+
+// zwlr_layer_surface_v1 anchors
+pub const TOP:    u32 = 1;
+pub const BOTTOM: u32 = 2;
+pub const LEFT:   u32 = 4;
+pub const RIGHT:  u32 = 8;
+
+const BTN_LEFT: u32 = 0x110;
+
+/// The globals advertised, as (name, interface, version).
+const GLOBALS: &[(u32, &str, u32)] = &[
+    (1, "wl_compositor", 4),
+    (2, "wl_shm", 1),
+    (3, "zwlr_layer_shell_v1", 1),
+    (4, "zxdg_output_manager_v1", 2),
+    (5, "wl_output", 3),
+    (6, "wl_seat", 1),
+];
+
+/// A layer surface, as set up by the client.
+#[derive(Clone, Debug, Default)]
+pub struct Layer {
+    pub surface:        u32,
+    pub namespace:      String,
+    pub anchor:         u32,
+    pub exclusive_zone: i32,
+    pub size:           (u32, u32),
+    pub margin:         (i32, i32, i32, i32), // (top, right, bottom, left)
+}
+
+#[derive(Default)]
+pub struct State {
+    objects:      HashMap<u32, &'static str>, // interfaces, by id
+    layers:       Vec<(u32, Layer)>, // by id, oldest first
+    pointer:      Option<u32>,
+
+    /// The buffers attached to surfaces, in order.
+    pub attached: Vec<u32>,
+}
+
+impl State {
+
+    /// Get the layer surface created last.
+    pub fn layer(&self) -> &Layer {
+        &self.layers.last().expect("no layer surface").1
+    }
+
+    pub fn layer_count(&self) -> usize {
+        self.layers.len()
+    }
+
+    fn layer_mut(&mut self, id: u32) -> &mut Layer {
+        &mut self.layers.iter_mut().find(|&&mut (i, _)| i == id)
+                 .expect("unknown layer surface").1
+    }
+}
+
+/// An argument of an event.
+enum Arg<'a> {
+    Uint(u32),
+    Int(i32),
+    Fixed(f64),
+    Str(&'a str),
+}
+
+/// Write an event on `id` to the client.
+fn send(stream: &Mutex<UnixStream>, id: u32, opcode: u16, args: &[Arg]) {
+    let mut body = Vec::new();
+    for a in args {
+        match *a {
+            Arg::Uint(v)  => body.extend_from_slice(&v.to_ne_bytes()),
+            Arg::Int(v)   => body.extend_from_slice(&v.to_ne_bytes()),
+            Arg::Fixed(v) => {
+                body.extend_from_slice(&((v * 256.0) as i32).to_ne_bytes())
+            }
+            Arg::Str(s)   => {
+                let len = s.len() as u32 + 1;
+                body.extend_from_slice(&len.to_ne_bytes());
+                body.extend_from_slice(s.as_bytes());
+                body.push(0);
+                while body.len() % 4 != 0 {
+                    body.push(0);
+                }
+            }
+        }
+    }
+
+    let size = (body.len() + 8) as u32;
+    let mut msg = Vec::with_capacity(size as usize);
+    msg.extend_from_slice(&id.to_ne_bytes());
+    msg.extend_from_slice(&(size << 16 | opcode as u32).to_ne_bytes());
+    msg.extend_from_slice(&body);
+
+    // The client may be gone at the end of a test
+    let _ = stream.lock().unwrap().write_all(&msg);
+}
+
+/// Reads the arguments of a request.
+struct Args<'a> {
+    data: &'a [u8],
+    pos:  usize,
+}
+
+impl<'a> Args<'a> {
+    fn uint(&mut self) -> u32 {
+        let mut b = [0; 4];
+        b.copy_from_slice(&self.data[self.pos..self.pos + 4]);
+        self.pos += 4;
+        u32::from_ne_bytes(b)
+    }
+
+    fn int(&mut self) -> i32 {
+        self.uint() as i32
+    }
+
+    fn string(&mut self) -> String {
+        let len = self.uint() as usize;
+        let s = &self.data[self.pos..self.pos + len.saturating_sub(1)];
+        self.pos += (len + 3) / 4 * 4;
+        String::from_utf8_lossy(s).into_owned()
+    }
+}
+
+pub struct Compositor {
+    state:  Arc<Mutex<State>>,
+    stream: Arc<Mutex<UnixStream>>,
+}
+
+impl Compositor {
+
+    /// Start the compositor, and get the file descriptor of the client
+    /// end of its socket, which is for `WAYLAND_SOCKET`.
+    pub fn start() -> (Compositor, RawFd) {
+        let (server, client) = UnixStream::pair().unwrap();
+        let reader = server.try_clone().unwrap();
+
+        let c = Compositor {
+            state:  Arc::new(Mutex::new(State::default())),
+            stream: Arc::new(Mutex::new(server)),
+        };
+
+        let state = c.state.clone();
+        let stream = c.stream.clone();
+        thread::spawn(move || serve(reader, &state, &stream));
+
+        (c, client.into_raw_fd())
+    }
+
+    pub fn state(&self) -> MutexGuard<State> {
+        self.state.lock().unwrap()
+    }
+
+    /// Wait for the requests of the client to bring the state to `f`,
+    /// returning false if they do not within a second.
+    pub fn wait<F: Fn(&State) -> bool>(&self, f: F) -> bool {
+        let start = Instant::now();
+        while start.elapsed() < Duration::from_secs(1) {
+            if f(&self.state()) {
+                return true;
+            }
+            thread::sleep(Duration::from_millis(5));
+        }
+
+        false
+    }
+
+    /// Click with the left button at `(x, y)` in the current surface.
+    pub fn click(&self, x: f64, y: f64) {
+        // The pointer is asked for as the seat is received, after the
+        // roundtrips of the client
+        assert!(self.wait(|st| st.pointer.is_some()), "no pointer");
+
+        let (pointer, surface) = {
+            let st = self.state();
+            (st.pointer.unwrap(), st.layer().surface)
+        };
+
+        send(&self.stream, pointer, 0, &[
+             Arg::Uint(1), Arg::Uint(surface), Arg::Fixed(x), Arg::Fixed(y),
+        ]);
+        for &pressed in &[1, 0] {
+            send(&self.stream, pointer, 3, &[
+                 Arg::Uint(2), Arg::Uint(10), Arg::Uint(BTN_LEFT),
+                 Arg::Uint(pressed),
+            ]);
+        }
+        send(&self.stream, pointer, 1, &[Arg::Uint(3), Arg::Uint(surface)]);
+    }
+
+    /// Give a buffer back to the client.
+    pub fn release(&self, buffer: u32) {
+        send(&self.stream, buffer, 0, &[]);
+    }
+}
+
+/// Handle the requests of the client until it disconnects.
+fn serve(mut reader: UnixStream, state: &Mutex<State>,
+         stream: &Mutex<UnixStream>) {
+    state.lock().unwrap().objects.insert(1, "wl_display");
+    let mut serial = 0;
+
+    loop {
+        let mut header = [0; 8];
+        if reader.read_exact(&mut header).is_err() {
+            return;
+        }
+
+        let mut b = [0; 4];
+        b.copy_from_slice(&header[..4]);
+        let id = u32::from_ne_bytes(b);
+        b.copy_from_slice(&header[4..]);
+        let word = u32::from_ne_bytes(b);
+        let (size, opcode) = ((word >> 16) as usize, (word & 0xffff) as u16);
+
+        // File descriptors come along as ancillary data, and are dropped
+        let mut data = vec![0; size - 8];
+        if reader.read_exact(&mut data).is_err() {
+            return;
+        }
+        let mut args = Args { data: &data, pos: 0 };
+
+        let mut st = state.lock().unwrap();
+        let interface = match st.objects.get(&id) {
+            Some(&i) => i,
+            None     => continue,
+        };
+
+        match (interface, opcode) {
+            ("wl_display", 0) => {
+                send(stream, args.uint(), 0, &[Arg::Uint(0)]);
+            }
+
+            ("wl_display", 1) => {
+                let registry = args.uint();
+                st.objects.insert(registry, "wl_registry");
+                for &(name, interface, version) in GLOBALS {
+                    send(stream, registry, 0, &[
+                         Arg::Uint(name), Arg::Str(interface),
+                         Arg::Uint(version),
+                    ]);
+                }
+            }
+
+            ("wl_registry", 0) => {
+                let name = args.uint();
+                let _ = args.string();
+                let _ = args.uint();
+                let new = args.uint();
+
+                let &(_, interface, _) = GLOBALS.iter()
+                    .find(|&&(n, _, _)| n == name).unwrap();
+                st.objects.insert(new, interface);
+
+                // A pointer only
+                if interface == "wl_seat" {
+                    send(stream, new, 0, &[Arg::Uint(1)]);
+                }
+            }
+
+            ("wl_compositor", 0) => {
+                st.objects.insert(args.uint(), "wl_surface");
+            }
+
+            ("wl_shm", 0) => {
+                st.objects.insert(args.uint(), "wl_shm_pool");
+            }
+
+            ("wl_shm_pool", 0) => {
+                st.objects.insert(args.uint(), "wl_buffer");
+            }
+
+            ("wl_surface", 1) => {
+                let buffer = args.uint();
+                if buffer != 0 {
+                    st.attached.push(buffer);
+                }
+            }
+
+            ("wl_seat", 0) => {
+                let pointer = args.uint();
+                st.objects.insert(pointer, "wl_pointer");
+                st.pointer = Some(pointer);
+            }
+
+            ("zxdg_output_manager_v1", 1) => {
+                let xo = args.uint();
+                st.objects.insert(xo, "zxdg_output_v1");
+
+                send(stream, xo, 0, &[Arg::Int(0), Arg::Int(0)]);
+                send(stream, xo, 1, &[
+                     Arg::Int(OUTPUT_SIZE.0), Arg::Int(OUTPUT_SIZE.1),
+                ]);
+                send(stream, xo, 3, &[Arg::Str(OUTPUT)]);
+                send(stream, xo, 2, &[]);
+            }
+
+            ("zwlr_layer_shell_v1", 0) => {
+                let layer = args.uint();
+                let surface = args.uint();
+                let _output = args.uint();
+                let _layer = args.uint();
+                let namespace = args.string();
+
+                st.objects.insert(layer, "zwlr_layer_surface_v1");
+                st.layers.push((layer, Layer {
+                    surface,
+                    namespace,
+                    ..Layer::default()
+                }));
+
+                serial += 1;
+                send(stream, layer, 0, &[
+                     Arg::Uint(serial), Arg::Uint(0), Arg::Uint(0),
+                ]);
+            }
+
+            ("zwlr_layer_surface_v1", 0) => {
+                let size = (args.uint(), args.uint());
+                st.layer_mut(id).size = size;
+            }
+
+            ("zwlr_layer_surface_v1", 1) => {
+                st.layer_mut(id).anchor = args.uint();
+            }
+
+            ("zwlr_layer_surface_v1", 2) => {
+                st.layer_mut(id).exclusive_zone = args.int();
+            }
+
+            ("zwlr_layer_surface_v1", 3) => {
+                let margin = (args.int(), args.int(), args.int(), args.int());
+                st.layer_mut(id).margin = margin;
+            }
+
+            _ => {}
+        }
+    }
+}
@@ -0,0 +1,119 @@
+//! Tests of the wayland backend, against a compositor in the test.
+
+extern crate lemonade;
+
+#[path = "../../src/bin/lemon.rs"]
+#[allow(dead_code)]
+mod lemon;
+#[path = "../common/mod.rs"]
+mod common;
+mod compositor;
+
+use std::env;
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+use lemonade::Bar;
+use lemonade::window::{Dock, Edge, Wayland};
+use lemon::LemonParser;
+use common::commands;
+use compositor::{Compositor, OUTPUT, OUTPUT_SIZE, TOP, BOTTOM, LEFT, RIGHT};
+
+/// The environment is shared by the tests, which run at the same time.
+static ENV: Mutex<()> = Mutex::new(());
+
+/// Get a bar connected to a new compositor.
+fn connect() -> (Compositor, Bar<Wayland>) {
+    let _env = ENV.lock().unwrap_or_else(|e| e.into_inner());
+    let (compositor, fd) = Compositor::start();
+    env::set_var("WAYLAND_SOCKET", fd.to_string());
+
+    let bar = Bar::with_wayland().unwrap();
+    (compositor, bar)
+}
+
+#[test]
+fn layer_is_anchored_to_the_top() {
+    let (compositor, _bar) = connect();
+
+    let st = compositor.state();
+    let layer = st.layer();
+    assert_eq!(layer.namespace, "lemonade");
+    assert_eq!(layer.anchor, TOP | LEFT);
+    assert_eq!(layer.exclusive_zone, 25);
+    assert_eq!(layer.size, (OUTPUT_SIZE.0 as u32, 25));
+}
+
+#[test]
+fn layer_is_anchored_to_each_edge() {
+    let (compositor, mut bar) = connect();
+
+    bar.set_edge(Edge::Bottom).unwrap();
+    assert_eq!(compositor.state().layer().anchor, BOTTOM | LEFT);
+    assert_eq!(compositor.state().layer().exclusive_zone, 25);
+
+    bar.set_edge(Edge::Left).unwrap();
+    {
+        let st = compositor.state();
+        assert_eq!(st.layer().anchor, LEFT | TOP);
+        assert_eq!(st.layer().exclusive_zone, 25);
+        assert_eq!(st.layer().size, (25, OUTPUT_SIZE.1 as u32));
+    }
+
+    bar.set_edge(Edge::Right).unwrap();
+    assert_eq!(compositor.state().layer().anchor, RIGHT | TOP);
+}
+
+#[test]
+fn clicks_reach_the_bar() {
+    let (compositor, mut bar) = connect();
+    let cmds = commands(&mut bar);
+    bar.update(LemonParser::new().parse("%{A:a:}%{O50}%{A}%{O50}"));
+
+    compositor.click(25.0, 10.0);
+    compositor.click(75.0, 10.0);
+    bar.handle_events().unwrap();
+    assert_eq!(*cmds.lock().unwrap(), vec!["a"]);
+}
+
+#[test]
+fn clicks_reach_the_bar_after_recreating_the_surface() {
+    let (compositor, mut bar) = connect();
+    let cmds = commands(&mut bar);
+    bar.update(LemonParser::new().parse("%{A:a:}%{O50}%{A}"));
+
+    bar.window_mut().set_name("other").unwrap();
+    assert_eq!(compositor.state().layer_count(), 2);
+    assert_eq!(compositor.state().layer().namespace, "other");
+    compositor.click(25.0, 10.0);
+    bar.handle_events().unwrap();
+    assert_eq!(*cmds.lock().unwrap(), vec!["a"]);
+
+    bar.window_mut().set_output(OUTPUT).unwrap();
+    assert_eq!(compositor.state().layer_count(), 3);
+    compositor.click(25.0, 10.0);
+    bar.handle_events().unwrap();
+    assert_eq!(*cmds.lock().unwrap(), vec!["a", "a"]);
+}
+
+#[test]
+fn frames_wait_for_a_released_buffer() {
+    let (compositor, mut bar) = connect();
+    bar.update(LemonParser::new().parse("%{O50}"));
+    assert!(compositor.wait(|st| st.attached.len() == 1));
+    let first = compositor.state().attached[0];
+
+    // The second buffer is free, but not the third
+    bar.draw();
+    assert!(compositor.wait(|st| st.attached.len() == 2));
+    assert!(compositor.state().attached[1] != first);
+    bar.draw();
+    thread::sleep(Duration::from_millis(50));
+    assert_eq!(compositor.state().attached.len(), 2);
+
+    compositor.release(first);
+    bar.handle_events().unwrap();
+    assert!(compositor.wait(|st| st.attached.len() == 3));
+    assert_eq!(compositor.state().attached[2], first);
+}